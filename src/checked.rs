@@ -0,0 +1,82 @@
+use std::{fmt::Display, io};
+
+// Arithmetic for answers that can get big. Work is done in 128 bits, and
+// anything that still doesn't fit is reported instead of wrapping or panicking.
+
+pub fn overflow(operation: &str) -> io::Error {
+  io::Error::new(
+    io::ErrorKind::InvalidData,
+    format!("{} overflows", operation),
+  )
+}
+
+// Turns the result of a checked_* operation into an error when it overflowed
+pub fn checked<T>(value: Option<T>, operation: &str) -> io::Result<T> {
+  value.ok_or_else(|| overflow(operation))
+}
+
+// Converts back down to the type an answer is reported in
+pub fn narrow<T, U>(value: U) -> io::Result<T>
+where
+  T: TryFrom<U>,
+  U: Copy + Display,
+{
+  T::try_from(value).map_err(|_| overflow(&format!("Narrowing {}", value)))
+}
+
+pub fn gcd(mut a: u128, mut b: u128) -> u128 {
+  while b != 0 {
+    (a, b) = (b, a % b);
+  }
+  a
+}
+
+// Dividing first keeps the intermediate no bigger than the result
+pub fn lcm(a: u128, b: u128) -> io::Result<u128> {
+  if a == 0 || b == 0 {
+    return Ok(0);
+  }
+  checked((a / gcd(a, b)).checked_mul(b), "Least common multiple")
+}
+
+pub fn lcm_all(values: impl IntoIterator<Item = u128>) -> io::Result<u128> {
+  values.into_iter().try_fold(1, lcm)
+}
+
+pub fn product(values: impl IntoIterator<Item = u128>) -> io::Result<u128> {
+  values.into_iter().try_fold(1u128, |product, value| {
+    checked(product.checked_mul(value), "Product")
+  })
+}
+
+pub fn sum(values: impl IntoIterator<Item = u128>) -> io::Result<u128> {
+  values
+    .into_iter()
+    .try_fold(0u128, |sum, value| checked(sum.checked_add(value), "Sum"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn reports_overflow() {
+    assert_eq!(gcd(12, 18), 6);
+    assert_eq!(gcd(0, 5), 5);
+    assert_eq!(lcm_all([4, 6, 10]).unwrap(), 60);
+
+    // Both fit in a u64, but their product wouldn't before dividing
+    let big = 1 << 63;
+    assert_eq!(lcm(big, big / 2).unwrap(), big);
+    assert!(lcm(u64::MAX as u128, (u64::MAX - 2) as u128).unwrap() > u64::MAX as u128);
+    assert!(lcm(u128::MAX, u128::MAX - 1).is_err());
+
+    assert_eq!(product([1 << 60, 1 << 60]).unwrap(), 1 << 120);
+    assert!(product([1 << 64, 1 << 64]).is_err());
+    assert!(sum([u128::MAX, 1]).is_err());
+
+    assert_eq!(narrow::<u64, u128>(7).unwrap(), 7);
+    assert!(narrow::<u64, u128>(1 << 64).is_err());
+    assert!(narrow::<i64, i128>(i64::MIN as i128 - 1).is_err());
+  }
+}
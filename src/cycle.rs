@@ -0,0 +1,98 @@
+use crate::trace::Level;
+
+// Brent's cycle detection. Only ever keeps two states around, so the states don't
+// need to be hashable or cheap to store. Returns (prefix_len, period), where
+// prefix_len is the index of the first state that is part of the cycle.
+pub fn find_cycle<T, F>(initial: &T, mut step: F) -> (usize, usize)
+where
+  T: Clone + PartialEq,
+  F: FnMut(&T) -> T,
+{
+  let mut power = 1;
+  let mut period = 1;
+  let mut tortoise = initial.clone();
+  let mut hare = step(initial);
+
+  while tortoise != hare {
+    if power == period {
+      tortoise = hare.clone();
+      power *= 2;
+      period = 0;
+    }
+    hare = step(&hare);
+    period += 1;
+  }
+
+  // Keep the hare `period` steps ahead and walk both until they meet
+  let mut tortoise = initial.clone();
+  let mut hare = initial.clone();
+  for _ in 0..period {
+    hare = step(&hare);
+  }
+
+  let mut prefix_len = 0;
+  while tortoise != hare {
+    tortoise = step(&tortoise);
+    hare = step(&hare);
+    prefix_len += 1;
+  }
+
+  (prefix_len, period)
+}
+
+// The smallest index whose state is the same as the state at index n
+pub fn equivalent_index(n: usize, prefix_len: usize, period: usize) -> usize {
+  if n < prefix_len {
+    n
+  } else {
+    (n - prefix_len) % period + prefix_len
+  }
+}
+
+pub fn nth_state<T, F>(initial: &T, mut step: F, n: usize) -> T
+where
+  T: Clone + PartialEq,
+  F: FnMut(&T) -> T,
+{
+  let (prefix_len, period) = find_cycle(initial, &mut step);
+  trace!(
+    Level::Debug,
+    "Cycle of {} states after {} steps",
+    period,
+    prefix_len
+  );
+
+  let mut state = initial.clone();
+  for _ in 0..equivalent_index(n, prefix_len, period) {
+    state = step(&state);
+  }
+  state
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn finds_prefix_and_period() {
+    // 0 1 2 3 4 5 6 7 3 4 5 6 7 ...
+    let step = |n: &usize| if *n == 7 { 3 } else { n + 1 };
+
+    assert_eq!(find_cycle(&0, step), (3, 5));
+    assert_eq!(find_cycle(&3, step), (0, 5));
+    assert_eq!(find_cycle(&7, |n: &usize| *n), (0, 1));
+  }
+
+  #[test]
+  fn indexes_far_states() {
+    let step = |n: &usize| if *n == 7 { 3 } else { n + 1 };
+
+    assert_eq!(nth_state(&0, step, 2), 2);
+    assert_eq!(nth_state(&0, step, 8), 3);
+    assert_eq!(
+      nth_state(&0, step, 1_000_000_000),
+      3 + (1_000_000_000 - 3) % 5
+    );
+    assert_eq!(equivalent_index(1_000_000_000, 3, 5), 5);
+  }
+}
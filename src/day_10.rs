@@ -1,6 +1,9 @@
 use std::{collections::HashSet, fmt::Debug, io, vec};
 
-use crate::fs::read_day;
+use crate::{
+  fs::read_day,
+  trace::{self, Level},
+};
 
 #[derive(Clone, Copy, Eq, PartialEq, Hash)]
 struct Coordinate {
@@ -408,13 +411,16 @@ pub fn part_2() -> io::Result<usize> {
   let visited = path.iter().cloned().collect::<HashSet<_>>();
 
   let mut area_inside = 0;
+  // The drawing is only worth building when someone is going to see it
+  let draw = trace::enabled(Level::Debug);
 
   for row in 0..map.tiles.len() {
     let mut crossings = 0;
+    let mut drawing = String::new();
     for column in 0..map.tiles[row].len() {
       let coord = Coordinate::new(column as i32, row as i32);
 
-      if visited.contains(&coord) {
+      let shown = if visited.contains(&coord) {
         match map.get(coord) {
           Some(Tile::Pipe(PipeType::Vertical)) => {
             crossings += 1;
@@ -427,17 +433,21 @@ pub fn part_2() -> io::Result<usize> {
           }
           _ => {}
         }
-        print!("{}", map.get(coord).unwrap().to_char());
+        map.get(coord).unwrap().to_char()
+      } else if crossings % 2 == 1 {
+        area_inside += 1;
+        '░'
       } else {
-        if crossings % 2 == 1 {
-          area_inside += 1;
-          print!("░");
-        } else {
-          print!(" ");
-        }
+        ' '
+      };
+
+      if draw {
+        drawing.push(shown);
       }
     }
-    print!("\n");
+    if draw {
+      trace!(Level::Debug, "{}", drawing);
+    }
   }
 
   Ok(area_inside)
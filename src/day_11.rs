@@ -1,197 +1,291 @@
-use std::{collections::HashSet, fmt::Debug, io, vec};
+use std::io;
 
-use crate::fs::read_day;
+use crate::{checked::checked, fs::read_day};
 
-fn parse_input() -> io::Result<Vec<Vec<char>>> {
-  let result = read_day(11)?
-    .map(|line| line.trim().chars().collect())
-    .collect();
-
-  Ok(result)
-}
-
-#[derive(Clone, Copy)]
-struct Tile {
-  value: char,
-  xs_to_left: usize,
-  xs_to_top: usize,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Galaxy {
+  x: usize,
+  y: usize,
 }
 
-impl Debug for Tile {
-  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    write!(
-      f,
-      "[{}, {}, {}]",
-      self.value, self.xs_to_left, self.xs_to_top
-    )
+impl Galaxy {
+  fn distance(&self, other: &Galaxy) -> usize {
+    self.x.abs_diff(other.x) + self.y.abs_diff(other.y)
   }
 }
 
-fn handle_expansion(input: &mut Vec<Vec<char>>) -> Vec<Vec<Tile>> {
-  let width = input[0].len();
-  let height = input.len();
+// Only the galaxies and the empty rows and columns are kept, both sorted, so
+// expanding never has to touch the grid.
+pub struct Universe {
+  galaxies: Vec<Galaxy>,
+  empty_columns: Vec<usize>,
+  empty_rows: Vec<usize>,
+}
 
-  let all_vaccum_rows = input
-    .iter()
-    .enumerate()
-    .filter_map(|(index, row)| {
-      if row.iter().all(|c| *c == '.') {
-        Some(index)
-      } else {
-        None
+impl Universe {
+  fn parse(lines: impl Iterator<Item = String>) -> Self {
+    let mut galaxies = vec![];
+    let mut width = 0;
+    let mut height = 0;
+
+    for (y, line) in lines.enumerate() {
+      let line = line.trim();
+      width = width.max(line.len());
+      height = y + 1;
+      for (x, c) in line.chars().enumerate() {
+        if c == '#' {
+          galaxies.push(Galaxy { x, y });
+        }
       }
-    })
-    .collect::<Vec<_>>();
+    }
 
-  let all_vacuum_columns = {
-    (0..width).enumerate().filter_map(|(index, col_index)| {
-      if (0..height).all(|row_index| input[row_index][col_index] == '.') {
-        Some(index)
-      } else {
-        None
-      }
-    })
+    let empty_columns = (0..width)
+      .filter(|&x| galaxies.iter().all(|galaxy| galaxy.x != x))
+      .collect();
+    let empty_rows = (0..height)
+      .filter(|&y| galaxies.iter().all(|galaxy| galaxy.y != y))
+      .collect();
+
+    Self {
+      galaxies,
+      empty_columns,
+      empty_rows,
+    }
   }
-  .collect::<Vec<_>>();
 
-  for idx in &all_vaccum_rows {
-    let expanded = vec!['X'; width];
-    input[*idx] = expanded;
+  // Every empty column grows to `x_factor` columns and every empty row to
+  // `y_factor` rows. Galaxies keep their reading order. A factor of 0 would
+  // remove the empty lines, which the puzzle never asks for, so it is rejected.
+  pub fn expand(&self, x_factor: usize, y_factor: usize) -> io::Result<Vec<Galaxy>> {
+    let grown = |factor: usize| {
+      factor.checked_sub(1).ok_or(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "Expansion factor must be at least 1",
+      ))
+    };
+    let (x_grown, y_grown) = (grown(x_factor)?, grown(y_factor)?);
+    let shift = |coordinate: usize, empty_before: usize, grown: usize| {
+      checked(
+        empty_before
+          .checked_mul(grown)
+          .and_then(|extra| coordinate.checked_add(extra)),
+        "Expansion",
+      )
+    };
+
+    self
+      .galaxies
+      .iter()
+      .map(|galaxy| {
+        let empty_to_left = self.empty_columns.partition_point(|&x| x < galaxy.x);
+        let empty_to_top = self.empty_rows.partition_point(|&y| y < galaxy.y);
+
+        Ok(Galaxy {
+          x: shift(galaxy.x, empty_to_left, x_grown)?,
+          y: shift(galaxy.y, empty_to_top, y_grown)?,
+        })
+      })
+      .collect()
   }
+}
 
-  for idx in &all_vacuum_columns {
-    for row in input.iter_mut() {
-      row[*idx] = 'X';
-    }
+// Sum of |a - b| over all pairs. Once sorted, the i-th value is larger than the i
+// values before it, so it contributes value * i minus their sum.
+fn sum_of_differences(mut values: Vec<usize>) -> io::Result<usize> {
+  values.sort();
+
+  let mut total: usize = 0;
+  let mut prefix: usize = 0;
+  for (i, value) in values.into_iter().enumerate() {
+    // Never negative, the values before this one are no larger
+    let contribution = checked(value.checked_mul(i), "Distance")? - prefix;
+    total = checked(total.checked_add(contribution), "Sum of distances")?;
+    prefix = checked(prefix.checked_add(value), "Sum of distances")?;
   }
 
-  let all_vaccum_rows = all_vaccum_rows.into_iter().collect::<HashSet<_>>();
-  let all_vacuum_columns = all_vacuum_columns.into_iter().collect::<HashSet<_>>();
-
-  let mut result: Vec<Vec<Tile>> = Vec::new();
-
-  let mut xs_to_left = 0;
-  let mut xs_to_top = 0;
-
-  for row_index in 0..height {
-    if all_vaccum_rows.contains(&row_index) {
-      xs_to_top += 1;
-
-      let value = Tile {
-        value: 'X',
-        xs_to_left,
-        xs_to_top,
-      };
-
-      result.push(vec![value; width]);
-    } else {
-      let mut row = Vec::new();
-      for col_index in 0..width {
-        if all_vacuum_columns.contains(&col_index) {
-          xs_to_left += 1;
-          row.push(Tile {
-            value: 'X',
-            xs_to_left,
-            xs_to_top,
-          });
-        } else {
-          row.push(Tile {
-            value: input[row_index][col_index],
-            xs_to_left,
-            xs_to_top,
-          });
-        }
-      }
-      result.push(row);
-    }
-    xs_to_left = 0;
-  }
+  Ok(total)
+}
 
-  result
+pub fn sum_of_distances(galaxies: &[Galaxy]) -> io::Result<usize> {
+  let x = sum_of_differences(galaxies.iter().map(|galaxy| galaxy.x).collect())?;
+  let y = sum_of_differences(galaxies.iter().map(|galaxy| galaxy.y).collect())?;
+  checked(x.checked_add(y), "Sum of distances")
 }
 
-fn find_all_galaxies(input: &Vec<Vec<Tile>>) -> Vec<(Tile, usize, usize)> {
-  let width = input[0].len();
-  let height = input.len();
+// The pair furthest apart as (index, index, distance). Manhattan distance is the
+// larger of the spreads along the two diagonals, x + y and x - y.
+pub fn farthest_pair(galaxies: &[Galaxy]) -> Option<(usize, usize, usize)> {
+  let diagonals: [fn(&Galaxy) -> i64; 2] = [
+    |galaxy| galaxy.x as i64 + galaxy.y as i64,
+    |galaxy| galaxy.x as i64 - galaxy.y as i64,
+  ];
+
+  diagonals
+    .iter()
+    .filter_map(|diagonal| {
+      let (low, _) = galaxies
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, galaxy)| diagonal(galaxy))?;
+      let (high, _) = galaxies
+        .iter()
+        .enumerate()
+        .max_by_key(|(_, galaxy)| diagonal(galaxy))?;
+      Some((low, high, galaxies[low].distance(&galaxies[high])))
+    })
+    .filter(|(low, high, _)| low != high)
+    .max_by_key(|(_, _, distance)| *distance)
+}
 
-  let mut result = Vec::new();
+// The pair closest together as (index, index, distance), by divide and conquer.
+pub fn closest_pair(galaxies: &[Galaxy]) -> Option<(usize, usize, usize)> {
+  let mut indexed = galaxies.iter().copied().enumerate().collect::<Vec<_>>();
+  indexed.sort_by_key(|(_, galaxy)| galaxy.x);
+  closest_pair_sorted(&indexed)
+}
 
-  for row_index in 0..height {
-    for col_index in 0..width {
-      let tile = input[row_index][col_index];
-      if tile.value == '#' {
-        result.push((tile, col_index, row_index));
+fn closest_pair_sorted(galaxies: &[(usize, Galaxy)]) -> Option<(usize, usize, usize)> {
+  let closer = |a: Option<(usize, usize, usize)>, b: Option<(usize, usize, usize)>| match (a, b) {
+    (Some(a), Some(b)) => Some(if b.2 < a.2 { b } else { a }),
+    (a, b) => a.or(b),
+  };
+
+  if galaxies.len() <= 3 {
+    let mut best = None;
+    for (i, (a, u)) in galaxies.iter().enumerate() {
+      for (b, v) in &galaxies[i + 1..] {
+        best = closer(best, Some((*a, *b, u.distance(v))));
       }
     }
+    return best;
   }
 
-  result
-}
+  let middle = galaxies.len() / 2;
+  let middle_x = galaxies[middle].1.x;
+  let mut best = closer(
+    closest_pair_sorted(&galaxies[..middle]),
+    closest_pair_sorted(&galaxies[middle..]),
+  );
+  let (_, _, distance) = best.unwrap();
+
+  // Only pairs straddling the middle and closer than `distance` on both axes can
+  // still win, and there are only a few of those per galaxy.
+  let mut strip = galaxies
+    .iter()
+    .filter(|(_, galaxy)| galaxy.x.abs_diff(middle_x) < distance)
+    .collect::<Vec<_>>();
+  strip.sort_by_key(|(_, galaxy)| galaxy.y);
 
-fn distance_between(
-  u: &(Tile, usize, usize),
-  v: &(Tile, usize, usize),
-  value_per_x: usize,
-) -> usize {
-  let (t1, x1, y1) = u;
-  let (t2, x2, y2) = v;
-  let x = if x1 > x2 {
-    let diff = x1 - x2;
-    let xs = t1.xs_to_left - t2.xs_to_left;
-    diff + (xs * value_per_x)
-  } else {
-    let diff = x2 - x1;
-    let xs = t2.xs_to_left - t1.xs_to_left;
-    diff + (xs * value_per_x)
-  };
+  for (i, (a, u)) in strip.iter().enumerate() {
+    for (b, v) in &strip[i + 1..] {
+      if v.y - u.y >= distance {
+        break;
+      }
+      best = closer(best, Some((*a, *b, u.distance(v))));
+    }
+  }
 
-  let y = if y1 > y2 {
-    let diff = y1 - y2;
-    let ys = t1.xs_to_top - t2.xs_to_top;
-    diff + (ys * value_per_x)
-  } else {
-    let diff = y2 - y1;
-    let ys = t2.xs_to_top - t1.xs_to_top;
-    diff + (ys * value_per_x)
-  };
+  best
+}
 
-  x + y
+fn parse_input() -> io::Result<Universe> {
+  Ok(Universe::parse(read_day(11)?))
 }
 
 pub fn part_1() -> io::Result<usize> {
-  let mut input = parse_input()?;
-  let with_expansion = handle_expansion(&mut input);
+  let universe = parse_input()?;
+  sum_of_distances(&universe.expand(2, 2)?)
+}
 
-  let galaxy_positions = find_all_galaxies(&with_expansion);
+pub fn part_2() -> io::Result<usize> {
+  let universe = parse_input()?;
+  sum_of_distances(&universe.expand(1_000_000, 1_000_000)?)
+}
 
-  let value_per_x = 1;
-  let mut total = 0;
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const EXAMPLE: &str = "...#......
+.......#..
+#.........
+..........
+......#...
+.#........
+.........#
+..........
+.......#..
+#...#.....";
+
+  fn parse(input: &str) -> Universe {
+    Universe::parse(input.lines().map(String::from))
+  }
 
-  for x in &galaxy_positions {
-    for y in &galaxy_positions {
-      let distance = distance_between(x, y, value_per_x);
-      total += distance;
+  fn all_pairs(galaxies: &[Galaxy]) -> Vec<usize> {
+    let mut distances = vec![];
+    for (i, a) in galaxies.iter().enumerate() {
+      for b in &galaxies[i + 1..] {
+        distances.push(a.distance(b));
+      }
     }
+    distances
   }
 
-  Ok(total / 2)
-}
+  #[test]
+  fn sums_distances() {
+    let universe = parse(EXAMPLE);
+
+    assert_eq!(
+      sum_of_distances(&universe.expand(2, 2).unwrap()).unwrap(),
+      374
+    );
+    assert_eq!(
+      sum_of_distances(&universe.expand(10, 10).unwrap()).unwrap(),
+      1030
+    );
+    assert_eq!(
+      sum_of_distances(&universe.expand(100, 100).unwrap()).unwrap(),
+      8410
+    );
+
+    let galaxies = universe.expand(3, 7).unwrap();
+    assert_eq!(
+      sum_of_distances(&galaxies).unwrap(),
+      all_pairs(&galaxies).iter().sum::<usize>()
+    );
+  }
 
-pub fn part_2() -> io::Result<usize> {
-  let mut input = parse_input()?;
-  let with_expansion = handle_expansion(&mut input);
+  #[test]
+  fn finds_extreme_pairs() {
+    let universe = parse(EXAMPLE);
 
-  let galaxy_positions = find_all_galaxies(&with_expansion);
+    for (x_factor, y_factor) in [(1, 1), (2, 2), (5, 1), (1, 1_000)] {
+      let galaxies = universe.expand(x_factor, y_factor).unwrap();
+      let distances = all_pairs(&galaxies);
 
-  let value_per_x = 999_999;
-  let mut total = 0;
+      let (a, b, closest) = closest_pair(&galaxies).unwrap();
+      assert_eq!(closest, *distances.iter().min().unwrap());
+      assert_eq!(galaxies[a].distance(&galaxies[b]), closest);
 
-  for x in &galaxy_positions {
-    for y in &galaxy_positions {
-      let distance = distance_between(x, y, value_per_x);
-      total += distance;
+      let (a, b, farthest) = farthest_pair(&galaxies).unwrap();
+      assert_eq!(farthest, *distances.iter().max().unwrap());
+      assert_eq!(galaxies[a].distance(&galaxies[b]), farthest);
     }
+
+    assert_eq!(closest_pair(&[]), None);
+    assert_eq!(farthest_pair(&[Galaxy { x: 1, y: 1 }]), None);
   }
 
-  Ok(total / 2)
+  #[test]
+  fn rejects_bad_factors() {
+    let universe = parse(EXAMPLE);
+
+    assert!(universe.expand(0, 2).is_err());
+    assert!(universe.expand(2, 0).is_err());
+    assert!(universe.expand(usize::MAX, 1).is_err());
+
+    // Expanding fits, but adding up the distances doesn't
+    let galaxies = universe.expand(usize::MAX / 5, 1).unwrap();
+    assert!(sum_of_distances(&galaxies).is_err());
+  }
 }
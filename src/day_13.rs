@@ -58,78 +58,213 @@ impl Map {
       .map(|tiles| Map { tiles })
       .collect::<Vec<_>>();
 
+    // Rows and columns are packed into u64s
+    if result
+      .iter()
+      .any(|map| map.tiles.len() > 64 || map.tiles.iter().any(|row| row.len() > 64))
+    {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Maps can be at most 64 tiles across",
+      ));
+    }
+
     Ok(result)
   }
 
-  fn transpose(&self) -> Self {
-    let current_width = self.tiles[0].len();
-    let current_height = self.tiles.len();
-
-    let mut new_tiles = vec![vec![Tile::Ash; current_height]; current_width];
+  // Bit i of a row is column i, bit i of a column is row i
+  fn rows(&self) -> Vec<u64> {
+    self
+      .tiles
+      .iter()
+      .map(|row| {
+        row
+          .iter()
+          .enumerate()
+          .filter(|(_, tile)| **tile == Tile::Rock)
+          .fold(0, |bits, (x, _)| bits | 1 << x)
+      })
+      .collect()
+  }
 
-    for (x, row) in self.tiles.iter().enumerate() {
-      for (y, tile) in row.iter().enumerate() {
-        new_tiles[y][x] = *tile;
-      }
-    }
+  fn columns(&self) -> Vec<u64> {
+    let width = self.tiles.first().map_or(0, |row| row.len());
+    (0..width)
+      .map(|x| {
+        self
+          .tiles
+          .iter()
+          .enumerate()
+          .filter(|(_, row)| row[x] == Tile::Rock)
+          .fold(0, |bits, (y, _)| bits | 1 << y)
+      })
+      .collect()
+  }
 
-    Map { tiles: new_tiles }
+  // Every possible mirror line, each with the tiles that don't match up
+  pub fn reflections(&self) -> Vec<Reflection> {
+    let mut result = reflections_along(&self.rows(), Axis::Horizontal);
+    result.extend(reflections_along(&self.columns(), Axis::Vertical));
+    result
   }
 }
 
-fn find_mirror(map: &Map, diff: usize) -> Option<usize> {
-  for i in 1..map.tiles.len() {
-    // if mirror is between (i - 1) and i
-    let lower = map.tiles.iter().skip(i);
-    let flipped_upper = map.tiles.iter().take(i).rev();
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+  // Mirror line between two rows
+  Horizontal,
+  // Mirror line between two columns
+  Vertical,
+}
 
-    let differences = lower
-      .zip(flipped_upper)
-      .map(|(lower, upper)| {
-        lower
-          .iter()
-          .zip(upper.iter())
-          .filter(|(lower, upper)| lower != upper)
-          .count()
-      })
-      .sum::<usize>();
+// Pair of (x, y) tiles that should mirror each other but don't. Flipping either
+// tile of a pair fixes it, so a single pair is a smudge.
+pub type Mismatch = ((usize, usize), (usize, usize));
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reflection {
+  axis: Axis,
+  // Rows above or columns left of the line
+  index: usize,
+  mismatches: Vec<Mismatch>,
+}
+
+impl Reflection {
+  pub fn axis(&self) -> Axis {
+    self.axis
+  }
+
+  pub fn index(&self) -> usize {
+    self.index
+  }
+
+  pub fn mismatches(&self) -> &[Mismatch] {
+    &self.mismatches
+  }
+
+  pub fn mismatch_count(&self) -> usize {
+    self.mismatches.len()
+  }
+
+  // The (x, y) of the tile to flip when exactly one pair is off. Either tile
+  // would do, this is the one above or left of the line.
+  pub fn smudge(&self) -> Option<(usize, usize)> {
+    match self.mismatches[..] {
+      [(tile, _)] => Some(tile),
+      _ => None,
+    }
+  }
 
-    if differences == diff {
-      return Some(i);
+  pub fn summary(&self) -> usize {
+    match self.axis {
+      Axis::Horizontal => self.index * 100,
+      Axis::Vertical => self.index,
     }
   }
-  None
 }
 
-pub fn part_1() -> io::Result<usize> {
-  let maps = Map::from_file()?;
+fn reflections_along(lines: &[u64], axis: Axis) -> Vec<Reflection> {
+  (1..lines.len())
+    .map(|index| {
+      let mut mismatches = vec![];
 
-  let result = maps
+      for (before, after) in (0..index).rev().zip(index..lines.len()) {
+        let mut differences = lines[before] ^ lines[after];
+        while differences != 0 {
+          let along = differences.trailing_zeros() as usize;
+          differences &= differences - 1;
+
+          mismatches.push(match axis {
+            Axis::Horizontal => ((along, before), (along, after)),
+            Axis::Vertical => ((before, along), (after, along)),
+          });
+        }
+      }
+
+      Reflection {
+        axis,
+        index,
+        mismatches,
+      }
+    })
+    .collect()
+}
+
+fn summarize(maps: &[Map], smudges: usize) -> io::Result<usize> {
+  maps
     .iter()
     .map(|map| {
-      println!("{:?}", map);
-      find_mirror(&map, 0)
-        .map(|m| m * 100)
-        .or_else(|| find_mirror(&map.transpose(), 0))
-        .unwrap()
+      map
+        .reflections()
+        .iter()
+        .find(|reflection| reflection.mismatch_count() == smudges)
+        .map(|reflection| reflection.summary())
+        .ok_or(io::Error::new(
+          io::ErrorKind::InvalidData,
+          format!("No mirror with {} smudges", smudges),
+        ))
     })
-    .sum::<usize>();
-  Ok(result)
+    .sum()
+}
+
+pub fn part_1() -> io::Result<usize> {
+  let maps = Map::from_file()?;
+  summarize(&maps, 0)
 }
 
 pub fn part_2() -> io::Result<usize> {
   let maps = Map::from_file()?;
+  summarize(&maps, 1)
+}
 
-  let result = maps
-    .iter()
-    .map(|map| {
-      println!("{:?}", map);
-      find_mirror(&map, 1)
-        .map(|m| m * 100)
-        .or_else(|| find_mirror(&map.transpose(), 1))
-        .unwrap()
-    })
-    .sum::<usize>();
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse(input: &str) -> Map {
+    let tiles = input
+      .lines()
+      .map(|line| {
+        line
+          .chars()
+          .map(|c| if c == '#' { Tile::Rock } else { Tile::Ash })
+          .collect()
+      })
+      .collect();
+    Map { tiles }
+  }
 
-  Ok(result)
+  #[test]
+  fn finds_reflections_and_smudges() {
+    let map = parse(
+      "#.##..##.
+..#.##.#.
+##......#
+##......#
+..#.##.#.
+..##..##.
+#.#.##.#.",
+    );
+
+    let reflections = map.reflections();
+    assert_eq!(reflections.len(), 6 + 8);
+
+    let perfect = reflections
+      .iter()
+      .filter(|reflection| reflection.mismatch_count() == 0)
+      .collect::<Vec<_>>();
+    assert_eq!(perfect.len(), 1);
+    assert_eq!((perfect[0].axis(), perfect[0].index()), (Axis::Vertical, 5));
+    assert_eq!(perfect[0].smudge(), None);
+
+    let smudged = reflections
+      .iter()
+      .filter(|reflection| reflection.mismatch_count() == 1)
+      .collect::<Vec<_>>();
+    assert_eq!(smudged.len(), 1);
+    assert_eq!(smudged[0].summary(), 300);
+    assert_eq!(smudged[0].mismatches(), [((0, 0), (0, 5))]);
+    assert_eq!(smudged[0].smudge(), Some((0, 0)));
+    assert_eq!(smudged[0].axis(), Axis::Horizontal);
+  }
 }
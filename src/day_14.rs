@@ -1,157 +1,305 @@
-use std::{collections::HashMap, io};
+use std::{fmt::Debug, io};
 
-use crate::fs::read_day;
+use crate::{cycle::nth_state, fs::read_day};
 
-fn read_input() -> io::Result<Vec<Vec<char>>> {
-  let result = read_day(14)?
-    .map(|line| line.chars().collect())
-    .collect::<Vec<Vec<char>>>();
-  Ok(result)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+  North,
+  West,
+  South,
+  East,
 }
 
-fn rotate_left(matrix: &mut Vec<Vec<char>>) {
-  let mut new_matrix = vec![vec!['.'; matrix.len()]; matrix.len()];
-  for i in 0..matrix.len() {
-    for j in 0..matrix.len() {
-      new_matrix[i][j] = matrix[j][matrix.len() - i - 1];
-    }
+// Bits lo..hi of a row
+fn mask(lo: usize, hi: usize) -> u128 {
+  if hi - lo == 128 {
+    !0
+  } else {
+    ((1 << (hi - lo)) - 1) << lo
   }
-  *matrix = new_matrix;
 }
 
-fn rotate_right(matrix: &mut Vec<Vec<char>>) {
-  let mut new_matrix = vec![vec!['.'; matrix.len()]; matrix.len()];
-  for i in 0..matrix.len() {
-    for j in 0..matrix.len() {
-      new_matrix[i][j] = matrix[matrix.len() - j - 1][i];
+// One u128 per row, with bit x set when there is a rock in column x. Small enough
+// that comparing and cloning whole states stays cheap during cycle detection.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Platform {
+  width: usize,
+  height: usize,
+  round: Vec<u128>,
+  cubes: Vec<u128>,
+}
+
+impl Debug for Platform {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    for y in 0..self.height {
+      for x in 0..self.width {
+        if self.round[y] & (1 << x) != 0 {
+          write!(f, "O")?;
+        } else if self.cubes[y] & (1 << x) != 0 {
+          write!(f, "#")?;
+        } else {
+          write!(f, ".")?;
+        }
+      }
+      writeln!(f)?;
     }
+    Ok(())
   }
-  *matrix = new_matrix;
 }
 
-fn push_left(matrix: &Vec<Vec<char>>) -> Vec<Vec<char>> {
-  matrix
-    .iter()
-    .map(|row| {
-      row
-        .split(|c| *c == '#')
-        .map(|group| {
-          let mut new_group = group.to_vec();
-          new_group.sort_by(|a, b| b.cmp(a));
-          new_group.iter().collect::<String>()
-        })
-        .collect::<Vec<String>>()
-        .join("#")
-        .chars()
-        .collect::<Vec<char>>()
-    })
-    .collect::<Vec<Vec<char>>>()
-}
+impl Platform {
+  pub fn parse(lines: impl Iterator<Item = String>) -> io::Result<Platform> {
+    let mut platform = Platform {
+      width: 0,
+      height: 0,
+      round: vec![],
+      cubes: vec![],
+    };
 
-fn push_north(matrix: &mut Vec<Vec<char>>) -> Vec<Vec<char>> {
-  rotate_left(matrix);
-  let mut matrix = push_left(matrix);
-  rotate_right(&mut matrix);
-  matrix
-}
+    for line in lines {
+      if line.len() > 128 {
+        return Err(io::Error::new(
+          io::ErrorKind::InvalidData,
+          "Platform is wider than 128",
+        ));
+      }
+      if !platform.round.is_empty() && line.len() != platform.width {
+        return Err(io::Error::new(
+          io::ErrorKind::InvalidData,
+          "Rows have different lengths",
+        ));
+      }
+      platform.width = line.len();
 
-fn push_west(matrix: &mut Vec<Vec<char>>) -> Vec<Vec<char>> {
-  push_left(&matrix)
-}
+      let mut round = 0;
+      let mut cubes = 0;
+      for (x, c) in line.chars().enumerate() {
+        match c {
+          'O' => round |= 1 << x,
+          '#' => cubes |= 1 << x,
+          '.' => {}
+          _ => {
+            return Err(io::Error::new(
+              io::ErrorKind::InvalidData,
+              format!("Invalid tile {}", c),
+            ))
+          }
+        }
+      }
+      platform.round.push(round);
+      platform.cubes.push(cubes);
+    }
 
-fn push_south(matrix: &mut Vec<Vec<char>>) -> Vec<Vec<char>> {
-  rotate_right(matrix);
-  let mut matrix = push_left(matrix);
-  rotate_left(&mut matrix);
-  matrix
-}
+    platform.height = platform.round.len();
+    Ok(platform)
+  }
 
-fn push_east(matrix: &mut Vec<Vec<char>>) -> Vec<Vec<char>> {
-  rotate_left(matrix);
-  rotate_left(matrix);
-  let mut matrix = push_left(matrix);
-  rotate_right(&mut matrix);
-  rotate_right(&mut matrix);
-  matrix
-}
+  pub fn tilt(&mut self, side: Side) {
+    match side {
+      Side::North => self.tilt_vertically(true),
+      Side::South => self.tilt_vertically(false),
+      Side::West => self.tilt_horizontally(true),
+      Side::East => self.tilt_horizontally(false),
+    }
+  }
 
-fn cycle(matrix: &mut Vec<Vec<char>>) -> Vec<Vec<char>> {
-  let mut matrix = push_north(matrix);
-  matrix = push_west(&mut matrix);
-  matrix = push_south(&mut matrix);
-  matrix = push_east(&mut matrix);
+  // Rows are settled one at a time starting from the side we tilt towards, so all
+  // rocks in a row can be moved along together until they hit something.
+  fn tilt_vertically(&mut self, north: bool) {
+    let rows = if north {
+      (0..self.height).collect::<Vec<_>>()
+    } else {
+      (0..self.height).rev().collect::<Vec<_>>()
+    };
 
-  matrix
-}
+    for i in 1..rows.len() {
+      let mut moving = self.round[rows[i]];
+      self.round[rows[i]] = 0;
+
+      let mut j = i;
+      while moving != 0 {
+        let blocked = if j == 0 {
+          moving
+        } else {
+          moving & (self.round[rows[j - 1]] | self.cubes[rows[j - 1]])
+        };
+        self.round[rows[j]] |= blocked;
+        moving &= !blocked;
+        j = j.saturating_sub(1);
+      }
+    }
+  }
+
+  // Each run of cells between cube rocks just needs its rocks counted and packed
+  // against one end.
+  fn tilt_horizontally(&mut self, west: bool) {
+    for y in 0..self.height {
+      let cubes = self.cubes[y];
+      let mut round = 0;
+      let mut start = 0;
+
+      while start < self.width {
+        let ahead = cubes >> start;
+        let end = if ahead == 0 {
+          self.width
+        } else {
+          (start + ahead.trailing_zeros() as usize).min(self.width)
+        };
+
+        let count = (self.round[y] & mask(start, end)).count_ones() as usize;
+        round |= if west {
+          mask(start, start + count)
+        } else {
+          mask(end - count, end)
+        };
 
-fn print_matrix(matrix: &Vec<Vec<char>>) {
-  for line in matrix {
-    for c in line {
-      print!("{}", c);
+        start = end + 1;
+      }
+
+      self.round[y] = round;
+    }
+  }
+
+  pub fn spin_cycle(&mut self) {
+    for side in [Side::North, Side::West, Side::South, Side::East] {
+      self.tilt(side);
+    }
+  }
+
+  // Each rock weighs as much as its distance from the opposite edge
+  pub fn load(&self, side: Side) -> usize {
+    let mut result = 0;
+    for (y, row) in self.round.iter().enumerate() {
+      for x in 0..self.width {
+        if row & (1 << x) != 0 {
+          result += match side {
+            Side::North => self.height - y,
+            Side::South => y + 1,
+            Side::West => self.width - x,
+            Side::East => x + 1,
+          };
+        }
+      }
     }
-    print!("\n");
+    result
   }
 }
 
+fn read_input() -> io::Result<Platform> {
+  Platform::parse(read_day(14)?)
+}
+
 pub fn part_1() -> io::Result<usize> {
   let mut input = read_input()?;
-  rotate_left(&mut input);
-  let mut input = push_left(&input);
-  rotate_right(&mut input);
-
-  let height = input.len();
-
-  let result = input
-    .iter()
-    .enumerate()
-    .map(|(i, row)| {
-      let rock_count = row.iter().filter(|c| **c == 'O').count();
-      rock_count * (height - i)
-    })
-    .sum::<usize>();
-
-  Ok(result)
+  input.tilt(Side::North);
+  Ok(input.load(Side::North))
 }
 
 pub fn part_2() -> io::Result<usize> {
-  let mut input = read_input()?;
+  let input = read_input()?;
 
   let total_iterations: usize = 1000000000;
 
-  // Count how many iterations it took to loop
+  let after_iters = nth_state(
+    &input,
+    |platform| {
+      let mut platform = platform.clone();
+      platform.spin_cycle();
+      platform
+    },
+    total_iterations,
+  );
 
-  let mut seen_at_index = HashMap::new();
-  seen_at_index.insert(input.clone(), 0);
-  let mut matrices = vec![input.clone()];
-  let mut iterations: usize = 0;
+  Ok(after_iters.load(Side::North))
+}
 
-  let first = loop {
-    iterations += 1;
-    input = cycle(&mut input);
+#[cfg(test)]
+mod tests {
+  use super::*;
 
-    match seen_at_index.get(&input) {
-      Some(&first_seen) => break first_seen,
-      None => {
-        seen_at_index.insert(input.clone(), iterations);
-        matrices.push(input.clone());
-      }
-    }
-  };
+  const EXAMPLE: &str = "O....#....
+O.OO#....#
+.....##...
+OO.#O....O
+.O.....O#.
+O.#..O.#.#
+..O..#O..O
+.......O..
+#....###..
+#OO..#....";
+
+  fn parse(input: &str) -> Platform {
+    Platform::parse(input.lines().map(String::from)).unwrap()
+  }
+
+  #[test]
+  fn tilts_north() {
+    let mut platform = parse(EXAMPLE);
+    platform.tilt(Side::North);
 
-  let index = (total_iterations - first) % (iterations - first) + first;
+    assert_eq!(
+      platform,
+      parse(
+        "OOOO.#.O..
+OO..#....#
+OO..O##..O
+O..#.OO...
+........#.
+..#....#.#
+..O..#.O.O
+..O.......
+#....###..
+#....#...."
+      )
+    );
+    assert_eq!(platform.load(Side::North), 136);
+  }
+
+  #[test]
+  fn spins() {
+    let mut platform = parse(EXAMPLE);
+    platform.spin_cycle();
+
+    assert_eq!(
+      format!("{:?}", platform),
+      ".....#....
+....#...O#
+...OO##...
+.OO#......
+.....OOO#.
+.O#...O#.#
+....O#....
+......OOOO
+#...O###..
+#..OO#....
+"
+    );
 
-  let after_iters = matrices[index].clone();
+    let mut east = parse("O.O#.O\n.O..#O");
+    east.tilt(Side::East);
+    assert_eq!(east, parse(".OO#.O\n...O#O"));
+    assert_eq!(east.load(Side::East), 3 + 2 + 6 + 4 + 6);
+    assert_eq!(east.load(Side::West), 4 + 5 + 1 + 3 + 1);
+  }
 
-  let height = after_iters.len();
+  #[test]
+  fn finds_load_after_many_cycles() {
+    let platform = nth_state(
+      &parse(EXAMPLE),
+      |platform| {
+        let mut platform = platform.clone();
+        platform.spin_cycle();
+        platform
+      },
+      1000000000,
+    );
 
-  let result = after_iters
-    .iter()
-    .enumerate()
-    .map(|(i, row)| {
-      let rock_count = row.iter().filter(|c| **c == 'O').count();
-      rock_count * (height - i)
-    })
-    .sum::<usize>();
+    assert_eq!(platform.load(Side::North), 64);
+  }
 
-  Ok(result)
+  #[test]
+  fn rejects_ragged_rows() {
+    let lines = ["O.#", "O.", "..."].map(String::from);
+    assert!(Platform::parse(lines.into_iter()).is_err());
+  }
 }
@@ -1,12 +1,13 @@
 use std::{
-  collections::{HashSet, VecDeque},
+  collections::{HashMap, HashSet},
   io,
+  rc::Rc,
 };
 
-use crate::fs::read_day;
+use crate::{fs::read_day, trace::Level};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-enum Direction {
+pub enum Direction {
   Up,
   Down,
   Left,
@@ -27,10 +28,23 @@ impl Direction {
       _ => false,
     }
   }
+
+  fn bit(&self) -> u8 {
+    1 << *self as u8
+  }
+
+  fn clockwise(&self) -> Self {
+    match self {
+      Direction::Up => Direction::Right,
+      Direction::Right => Direction::Down,
+      Direction::Down => Direction::Left,
+      Direction::Left => Direction::Up,
+    }
+  }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-struct Position {
+pub struct Position {
   x: i64,
   y: i64,
 }
@@ -51,148 +65,391 @@ impl Position {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-struct Beam {
+pub struct Beam {
   position: Position,
   direction: Direction,
 }
 
-fn solve(matrix: &Vec<Vec<char>>, initial_beam: Beam) -> usize {
-  let width = matrix[0].len();
-  let height = matrix.len();
+impl Beam {
+  pub fn new(position: Position, direction: Direction) -> Self {
+    Self {
+      position,
+      direction,
+    }
+  }
 
-  let mut seen = HashSet::<Beam>::new();
+  fn heading(&self, direction: Direction) -> Self {
+    Beam::new(self.position, direction)
+  }
+}
 
-  let mut queue = VecDeque::new();
+pub trait OpticalElement {
+  // Pushes the beams leaving the tile once `beam` has landed on it
+  fn outputs(&self, beam: Beam, out: &mut Vec<Beam>);
+}
 
-  queue.push_back(initial_beam);
+pub struct Empty;
 
-  while let Some(beam) = queue.pop_front() {
-    let Beam {
-      position,
-      direction,
-    } = beam;
+impl OpticalElement for Empty {
+  fn outputs(&self, beam: Beam, out: &mut Vec<Beam>) {
+    out.push(beam);
+  }
+}
 
-    let Position { x, y } = position.move_in_direction(direction);
+#[derive(Debug, Clone, Copy)]
+pub enum Mirror {
+  Slash,
+  Backslash,
+}
 
-    // Check bounds
-    if x < 0 || x >= width as i64 || y < 0 || y >= height as i64 {
-      continue;
+impl Mirror {
+  fn reflect(&self, direction: Direction) -> Direction {
+    match (self, direction) {
+      (Mirror::Slash, Direction::Up) => Direction::Right,
+      (Mirror::Slash, Direction::Down) => Direction::Left,
+      (Mirror::Slash, Direction::Left) => Direction::Down,
+      (Mirror::Slash, Direction::Right) => Direction::Up,
+      (Mirror::Backslash, Direction::Up) => Direction::Left,
+      (Mirror::Backslash, Direction::Down) => Direction::Right,
+      (Mirror::Backslash, Direction::Left) => Direction::Up,
+      (Mirror::Backslash, Direction::Right) => Direction::Down,
     }
+  }
+}
 
-    let current = matrix[y as usize][x as usize];
+impl OpticalElement for Mirror {
+  fn outputs(&self, beam: Beam, out: &mut Vec<Beam>) {
+    out.push(beam.heading(self.reflect(beam.direction)));
+  }
+}
 
-    if current == '.'
-      || (current == '-' && direction.moving_horizontally())
-      || (current == '|' && direction.moving_vertically())
-    {
-      let beam = Beam {
-        position: Position::new(x, y),
-        direction,
-      };
-      // We just pass through this cell
-      if !seen.contains(&beam) {
-        seen.insert(beam);
-        queue.push_back(beam);
+#[derive(Debug, Clone, Copy)]
+pub enum Splitter {
+  Horizontal,
+  Vertical,
+}
+
+impl OpticalElement for Splitter {
+  fn outputs(&self, beam: Beam, out: &mut Vec<Beam>) {
+    match self {
+      Splitter::Horizontal if beam.direction.moving_vertically() => out.extend([
+        beam.heading(Direction::Left),
+        beam.heading(Direction::Right),
+      ]),
+      Splitter::Vertical if beam.direction.moving_horizontally() => {
+        out.extend([beam.heading(Direction::Up), beam.heading(Direction::Down)])
       }
-    } else if current == '/' {
-      let new_direction = match direction {
-        Direction::Up => Direction::Right,
-        Direction::Down => Direction::Left,
-        Direction::Left => Direction::Down,
-        Direction::Right => Direction::Up,
-      };
+      // We just pass through this cell
+      _ => out.push(beam),
+    }
+  }
+}
 
-      let beam = Beam {
-        position: Position::new(x, y),
-        direction: new_direction,
-      };
+pub struct Absorber;
 
-      if !seen.contains(&beam) {
-        seen.insert(beam);
-        queue.push_back(beam);
-      }
-    } else if current == '\\' {
-      let new_direction = match direction {
-        Direction::Up => Direction::Left,
-        Direction::Down => Direction::Right,
-        Direction::Left => Direction::Up,
-        Direction::Right => Direction::Down,
-      };
+impl OpticalElement for Absorber {
+  fn outputs(&self, _: Beam, _: &mut Vec<Beam>) {}
+}
 
-      let beam = Beam {
-        position: Position::new(x, y),
-        direction: new_direction,
-      };
+// Beams travelling in one of the `reflecting` directions hit the silvered face,
+// the others pass straight through the back of the mirror.
+pub struct OneWayMirror {
+  pub mirror: Mirror,
+  pub reflecting: [Direction; 2],
+}
 
-      if !seen.contains(&beam) {
-        seen.insert(beam);
-        queue.push_back(beam);
-      }
+impl OpticalElement for OneWayMirror {
+  fn outputs(&self, beam: Beam, out: &mut Vec<Beam>) {
+    if self.reflecting.contains(&beam.direction) {
+      self.mirror.outputs(beam, out)
     } else {
-      let splitted_beams = if current == '-' && direction.moving_vertically() {
-        vec![
-          Beam {
-            position: Position::new(x, y),
-            direction: Direction::Left,
-          },
-          Beam {
-            position: Position::new(x, y),
-            direction: Direction::Right,
-          },
-        ]
-      } else if current == '|' && direction.moving_horizontally() {
-        vec![
-          Beam {
-            position: Position::new(x, y),
-            direction: Direction::Up,
-          },
-          Beam {
-            position: Position::new(x, y),
-            direction: Direction::Down,
-          },
-        ]
-      } else {
-        unreachable!("Invalid input: {}", current)
-      };
+      out.push(beam)
+    }
+  }
+}
+
+// Lets the beam through and sends a copy off to its right.
+pub struct Prism;
+
+impl OpticalElement for Prism {
+  fn outputs(&self, beam: Beam, out: &mut Vec<Beam>) {
+    out.extend([beam, beam.heading(beam.direction.clockwise())]);
+  }
+}
+
+// A beam landing on either end carries on in the same direction from the other.
+pub struct Teleport {
+  ends: (Position, Position),
+}
+
+impl OpticalElement for Teleport {
+  fn outputs(&self, beam: Beam, out: &mut Vec<Beam>) {
+    let (a, b) = self.ends;
+    let exit = if beam.position == a { b } else { a };
+    out.push(Beam::new(exit, beam.direction));
+  }
+}
+
+pub struct Optics {
+  elements: HashMap<char, Rc<dyn OpticalElement>>,
+  teleports: HashSet<char>,
+}
+
+impl Optics {
+  pub fn new() -> Self {
+    Self {
+      elements: HashMap::new(),
+      teleports: HashSet::new(),
+    }
+  }
+
+  // The tiles from the puzzle
+  pub fn standard() -> Self {
+    Self::new()
+      .register('.', Empty)
+      .register('-', Splitter::Horizontal)
+      .register('|', Splitter::Vertical)
+      .register('/', Mirror::Slash)
+      .register('\\', Mirror::Backslash)
+  }
+
+  pub fn register(mut self, tile: char, element: impl OpticalElement + 'static) -> Self {
+    self.elements.insert(tile, Rc::new(element));
+    self
+  }
+
+  // Each of these tiles has to appear exactly twice in the grid
+  pub fn register_teleport(mut self, tile: char) -> Self {
+    self.teleports.insert(tile);
+    self
+  }
 
-      for beam in splitted_beams {
-        if !seen.contains(&beam) {
-          seen.insert(beam);
-          queue.push_back(beam);
+  pub fn parse(&self, lines: impl Iterator<Item = String>) -> io::Result<Contraption> {
+    let mut tiles = vec![];
+    let mut teleport_ends = HashMap::<char, Vec<Position>>::new();
+
+    for (y, line) in lines.enumerate() {
+      let mut row = vec![];
+      for (x, tile) in line.chars().enumerate() {
+        let position = Position::new(x as i64, y as i64);
+        if self.teleports.contains(&tile) {
+          teleport_ends.entry(tile).or_default().push(position);
+          // Filled in once both ends are known
+          row.push(Rc::new(Empty) as Rc<dyn OpticalElement>);
+        } else {
+          let element = self.elements.get(&tile).ok_or(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unknown tile {}", tile),
+          ))?;
+          row.push(element.clone());
         }
       }
+      tiles.push(row);
+    }
+
+    for (tile, ends) in teleport_ends {
+      let [a, b] = ends[..] else {
+        return Err(io::Error::new(
+          io::ErrorKind::InvalidData,
+          format!("Teleport {} needs exactly two ends", tile),
+        ));
+      };
+      let teleport: Rc<dyn OpticalElement> = Rc::new(Teleport { ends: (a, b) });
+      tiles[a.y as usize][a.x as usize] = teleport.clone();
+      tiles[b.y as usize][b.x as usize] = teleport;
+    }
+
+    let width = tiles.first().map(|row| row.len()).unwrap_or(0);
+    if tiles.iter().any(|row| row.len() != width) {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Rows have different lengths",
+      ));
     }
+
+    Ok(Contraption {
+      width,
+      height: tiles.len(),
+      tiles,
+    })
   }
+}
 
-  let result = seen
-    .iter()
-    .map(|beam| beam.position)
-    .collect::<HashSet<_>>()
-    .len();
+// Where the cell sits in a row by row layout of the grid, if it is in the grid
+fn cell_index(width: usize, height: usize, position: Position) -> Option<usize> {
+  let Position { x, y } = position;
+  if x < 0 || x >= width as i64 || y < 0 || y >= height as i64 {
+    return None;
+  }
+  Some(y as usize * width + x as usize)
+}
 
-  result
+pub struct Contraption {
+  width: usize,
+  height: usize,
+  tiles: Vec<Vec<Rc<dyn OpticalElement>>>,
 }
 
-pub fn part_1() -> io::Result<usize> {
-  let matrix = read_day(16)?
-    .map(|line| line.chars().collect::<Vec<_>>())
-    .collect::<Vec<_>>();
+impl Contraption {
+  fn index(&self, position: Position) -> Option<usize> {
+    cell_index(self.width, self.height, position)
+  }
 
-  let initial_beam = Beam {
-    position: Position::new(-1, 0),
-    direction: Direction::Right,
-  };
+  // Moves the beam one step onto the tile in front of it, if there is one
+  fn land(&self, beam: Beam) -> Option<Beam> {
+    let Position { x, y } = beam.position.move_in_direction(beam.direction);
+
+    // Check bounds
+    if x < 0 || x >= self.width as i64 || y < 0 || y >= self.height as i64 {
+      return None;
+    }
+
+    Some(Beam::new(Position::new(x, y), beam.direction))
+  }
 
-  Ok(solve(&matrix, initial_beam))
+  fn outputs(&self, landed: Beam, out: &mut Vec<Beam>) {
+    let Position { x, y } = landed.position;
+    self.tiles[y as usize][x as usize].outputs(landed, out)
+  }
 }
 
-pub fn part_2() -> io::Result<usize> {
-  let matrix = read_day(16)?
-    .map(|line| line.chars().collect::<Vec<_>>())
-    .collect::<Vec<_>>();
+#[derive(Debug)]
+pub struct Trace {
+  width: usize,
+  height: usize,
+  // The directions beams travelled through each cell in, one bit per direction
+  energised: Vec<u8>,
+  // Beam states where a beam came back onto its own path
+  pub loops: Vec<Beam>,
+}
 
-  let width = matrix[0].len();
-  let height = matrix.len();
+impl Trace {
+  fn new(contraption: &Contraption) -> Self {
+    Self {
+      width: contraption.width,
+      height: contraption.height,
+      energised: vec![0; contraption.width * contraption.height],
+      loops: vec![],
+    }
+  }
+
+  pub fn energised_count(&self) -> usize {
+    self.energised.iter().filter(|&&mask| mask != 0).count()
+  }
+
+  // Empty for cells no beam went through, including any outside the grid
+  pub fn directions(&self, position: Position) -> Vec<Direction> {
+    let mask =
+      cell_index(self.width, self.height, position).map_or(0, |index| self.energised[index]);
+    [
+      Direction::Up,
+      Direction::Down,
+      Direction::Left,
+      Direction::Right,
+    ]
+    .into_iter()
+    .filter(|direction| mask & direction.bit() != 0)
+    .collect()
+  }
+
+  fn record(&mut self, contraption: &Contraption, beam: Beam) {
+    if let Some(index) = contraption.index(beam.position) {
+      self.energised[index] |= beam.direction.bit();
+    }
+  }
+
+  pub fn render(&self) -> String {
+    self
+      .energised
+      .chunks(self.width.max(1))
+      .map(|row| {
+        row
+          .iter()
+          .map(|&mask| if mask != 0 { '#' } else { '.' })
+          .collect::<String>()
+      })
+      .collect::<Vec<_>>()
+      .join("\n")
+  }
+}
+
+// Lands the beam and pushes whatever leaves the tile onto `pending`, in reverse
+// so popping them follows the tile's order
+fn expand(contraption: &Contraption, trace: &mut Trace, beam: Beam, pending: &mut Vec<Beam>) {
+  let start = pending.len();
+  if let Some(landed) = contraption.land(beam) {
+    trace.record(contraption, landed);
+    contraption.outputs(landed, pending);
+  }
+  for &output in &pending[start..] {
+    trace.record(contraption, output);
+  }
+  pending[start..].reverse();
+}
+
+// Emitters sit just before the cell they light up, like the initial beam in part 1.
+// The beams are followed depth first, so running into a state that is still on the
+// current path means the beam has looped.
+pub fn simulate(contraption: &Contraption, emitters: &[Beam]) -> Trace {
+  let mut trace = Trace::new(contraption);
+  // Beam states per cell, as direction bits like the trace
+  let mut on_path = vec![0u8; contraption.width * contraption.height];
+  let mut done = vec![0u8; contraption.width * contraption.height];
+
+  // Each frame remembers how much of `pending` was there before its outputs were
+  // pushed, so one buffer holds the unexplored beams of the whole path
+  let mut stack: Vec<(Beam, usize)> = vec![];
+  let mut pending = vec![];
+
+  for &emitter in emitters {
+    // Emitters usually sit outside the grid, where nothing is tracked
+    if let Some(index) = contraption.index(emitter.position) {
+      let bit = emitter.direction.bit();
+      if (on_path[index] | done[index]) & bit != 0 {
+        continue;
+      }
+      on_path[index] |= bit;
+    }
+    stack.push((emitter, pending.len()));
+    expand(contraption, &mut trace, emitter, &mut pending);
+
+    while let Some(&(beam, base)) = stack.last() {
+      if pending.len() == base {
+        stack.pop();
+        if let Some(index) = contraption.index(beam.position) {
+          on_path[index] &= !beam.direction.bit();
+          done[index] |= beam.direction.bit();
+        }
+        continue;
+      }
+
+      let beam = pending.pop().unwrap();
+      if let Some(index) = contraption.index(beam.position) {
+        let bit = beam.direction.bit();
+        if on_path[index] & bit != 0 {
+          trace.loops.push(beam);
+          continue;
+        }
+        if done[index] & bit != 0 {
+          continue;
+        }
+        on_path[index] |= bit;
+      }
+      stack.push((beam, pending.len()));
+      expand(contraption, &mut trace, beam, &mut pending);
+    }
+  }
+
+  trace
+}
+
+fn solve(contraption: &Contraption, initial_beam: Beam) -> usize {
+  simulate(contraption, &[initial_beam]).energised_count()
+}
+
+fn edge_beams(contraption: &Contraption) -> Vec<Beam> {
+  let width = contraption.width;
+  let height = contraption.height;
 
   let mut initial_beams = vec![];
 
@@ -228,11 +485,530 @@ pub fn part_2() -> io::Result<usize> {
     });
   }
 
-  let result = initial_beams
+  initial_beams
+}
+
+// The edge beam that energises the most tiles, together with that count.
+pub fn best_entry_point(contraption: &Contraption) -> Option<(Beam, usize)> {
+  edge_beams(contraption)
     .into_iter()
-    .map(|beam| solve(&matrix, beam))
-    .max()
-    .unwrap();
+    .map(|beam| (beam, solve(contraption, beam)))
+    .max_by_key(|(_, energised)| *energised)
+}
+
+#[derive(Debug, Clone)]
+struct CellSet {
+  width: usize,
+  words: Vec<u64>,
+}
+
+impl CellSet {
+  fn new(width: usize, height: usize) -> Self {
+    Self {
+      width,
+      words: vec![0; (width * height).div_ceil(64)],
+    }
+  }
+
+  fn insert(&mut self, position: Position) {
+    let index = position.y as usize * self.width + position.x as usize;
+    self.words[index / 64] |= 1 << (index % 64);
+  }
+
+  fn union_with(&mut self, other: &CellSet) {
+    for (word, other) in self.words.iter_mut().zip(other.words.iter()) {
+      *word |= other;
+    }
+  }
+
+  fn len(&self) -> usize {
+    self
+      .words
+      .iter()
+      .map(|word| word.count_ones() as usize)
+      .sum()
+  }
+}
+
+// A ray is the part of a beam's path that needs no branching. It ends when the
+// beam leaves the grid, comes back onto itself, or lands on a tile that splits it.
+struct Ray {
+  cells: Vec<Position>,
+  branch: Option<Vec<Beam>>,
+}
+
+fn trace_ray(contraption: &Contraption, mut beam: Beam) -> Ray {
+  let mut seen = HashSet::new();
+  let mut cells = vec![];
+  let mut next = vec![];
+
+  while let Some(landed) = contraption.land(beam) {
+    cells.push(landed.position);
+    next.clear();
+    contraption.outputs(landed, &mut next);
+    cells.extend(next.iter().map(|beam| beam.position));
+
+    if next.len() != 1 {
+      let branch = if next.is_empty() { None } else { Some(next) };
+      return Ray { cells, branch };
+    }
+
+    beam = next[0];
+    if !seen.insert(beam) {
+      break;
+    }
+  }
+
+  Ray {
+    cells,
+    branch: None,
+  }
+}
+
+// Every branching tile in the grid is a node, identified by the beams that leave
+// it. Nodes are condensed into strongly connected components so every node in a
+// loop of splitters shares one reachable set, computed once for the whole grid.
+pub struct BeamGraph {
+  width: usize,
+  height: usize,
+  nodes: HashMap<Vec<Beam>, usize>,
+  components: Vec<usize>,
+  reach: Vec<CellSet>,
+}
+
+impl BeamGraph {
+  pub fn new(contraption: &Contraption) -> Self {
+    let width = contraption.width;
+    let height = contraption.height;
+
+    // Find all branches by landing on every cell from every side
+    let mut nodes = HashMap::new();
+    for y in 0..height as i64 {
+      for x in 0..width as i64 {
+        for direction in [
+          Direction::Up,
+          Direction::Down,
+          Direction::Left,
+          Direction::Right,
+        ] {
+          let mut next = vec![];
+          contraption.outputs(Beam::new(Position::new(x, y), direction), &mut next);
+          if next.len() > 1 {
+            let id = nodes.len();
+            nodes.entry(next).or_insert(id);
+          }
+        }
+      }
+    }
+
+    let mut cells = vec![CellSet::new(width, height); nodes.len()];
+    let mut edges = vec![vec![]; nodes.len()];
+
+    for (branch, &id) in &nodes {
+      for &beam in branch {
+        cells[id].insert(beam.position);
+
+        let ray = trace_ray(contraption, beam);
+        for position in ray.cells {
+          cells[id].insert(position);
+        }
+        if let Some(next) = ray.branch {
+          edges[id].push(nodes[&next]);
+        }
+      }
+    }
+
+    let mut tarjan = Tarjan::new(&edges);
+    for node in 0..edges.len() {
+      if tarjan.index[node].is_none() {
+        tarjan.connect(node);
+      }
+    }
+
+    // Tarjan finds components sinks first, so successors are always ready
+    let mut reach: Vec<CellSet> = vec![];
+    for component in &tarjan.found {
+      let mut set = CellSet::new(width, height);
+      for &node in component {
+        set.union_with(&cells[node]);
+        for &next in &edges[node] {
+          let next_component = tarjan.components[next];
+          if next_component != reach.len() {
+            set.union_with(&reach[next_component]);
+          }
+        }
+      }
+      reach.push(set);
+    }
+
+    Self {
+      width,
+      height,
+      nodes,
+      components: tarjan.components,
+      reach,
+    }
+  }
+
+  pub fn energised(&self, contraption: &Contraption, emitter: Beam) -> usize {
+    let ray = trace_ray(contraption, emitter);
+
+    let mut set = match ray.branch {
+      Some(branch) => self.reach[self.components[self.nodes[&branch]]].clone(),
+      None => CellSet::new(self.width, self.height),
+    };
+    for position in ray.cells {
+      set.insert(position);
+    }
+
+    set.len()
+  }
+
+  pub fn best_entry_point(&self, contraption: &Contraption) -> Option<(Beam, usize)> {
+    edge_beams(contraption)
+      .into_iter()
+      .map(|beam| (beam, self.energised(contraption, beam)))
+      .max_by_key(|(_, energised)| *energised)
+  }
+}
+
+struct Tarjan<'a> {
+  edges: &'a [Vec<usize>],
+  next_index: usize,
+  index: Vec<Option<usize>>,
+  low_link: Vec<usize>,
+  on_stack: Vec<bool>,
+  stack: Vec<usize>,
+  components: Vec<usize>,
+  found: Vec<Vec<usize>>,
+}
+
+impl<'a> Tarjan<'a> {
+  fn new(edges: &'a [Vec<usize>]) -> Self {
+    Self {
+      edges,
+      next_index: 0,
+      index: vec![None; edges.len()],
+      low_link: vec![0; edges.len()],
+      on_stack: vec![false; edges.len()],
+      stack: vec![],
+      components: vec![0; edges.len()],
+      found: vec![],
+    }
+  }
+
+  fn visit(&mut self, node: usize) {
+    self.index[node] = Some(self.next_index);
+    self.low_link[node] = self.next_index;
+    self.next_index += 1;
+    self.stack.push(node);
+    self.on_stack[node] = true;
+  }
+
+  // The recursion is kept on an explicit stack of (node, next edge) pairs, so a
+  // long chain of splitters can't overflow the real one
+  fn connect(&mut self, root: usize) {
+    self.visit(root);
+    let mut calls = vec![(root, 0)];
+
+    while let Some((node, edge)) = calls.last_mut() {
+      let node = *node;
+      if let Some(&next) = self.edges[node].get(*edge) {
+        *edge += 1;
+        match self.index[next] {
+          None => {
+            self.visit(next);
+            calls.push((next, 0));
+          }
+          Some(index) if self.on_stack[next] => {
+            self.low_link[node] = self.low_link[node].min(index);
+          }
+          _ => {}
+        }
+        continue;
+      }
+
+      calls.pop();
+      if let Some(&(parent, _)) = calls.last() {
+        self.low_link[parent] = self.low_link[parent].min(self.low_link[node]);
+      }
+
+      if Some(self.low_link[node]) == self.index[node] {
+        let mut component = vec![];
+        loop {
+          let member = self.stack.pop().unwrap();
+          self.on_stack[member] = false;
+          self.components[member] = self.found.len();
+          component.push(member);
+          if member == node {
+            break;
+          }
+        }
+        self.found.push(component);
+      }
+    }
+  }
+}
+
+fn read_input() -> io::Result<Contraption> {
+  Optics::standard().parse(read_day(16)?)
+}
+
+pub fn part_1() -> io::Result<usize> {
+  let contraption = read_input()?;
+
+  let initial_beam = Beam {
+    position: Position::new(-1, 0),
+    direction: Direction::Right,
+  };
+
+  Ok(solve(&contraption, initial_beam))
+}
+
+pub fn part_2() -> io::Result<usize> {
+  let contraption = read_input()?;
+
+  let (beam, result) = BeamGraph::new(&contraption)
+    .best_entry_point(&contraption)
+    .ok_or(io::Error::new(
+      io::ErrorKind::InvalidData,
+      "No entry points",
+    ))?;
+  trace!(Level::Info, "Best entry point is {:?}", beam);
 
   Ok(result)
 }
+
+#[cfg(test)]
+mod tests {
+  use std::time::Instant;
+
+  use super::*;
+  use crate::test_util::Rng;
+
+  fn parse(input: &str) -> Contraption {
+    Optics::standard()
+      .parse(input.lines().map(String::from))
+      .unwrap()
+  }
+
+  const EXAMPLE: &str = r".|...\....
+|.-.\.....
+.....|-...
+........|.
+..........
+.........\
+..../.\\..
+.-.-/..|..
+.|....-|.\
+..//.|....";
+
+  #[test]
+  fn traces_example() {
+    let contraption = parse(EXAMPLE);
+    let trace = simulate(
+      &contraption,
+      &[Beam {
+        position: Position::new(-1, 0),
+        direction: Direction::Right,
+      }],
+    );
+
+    assert_eq!(trace.energised_count(), 46);
+    assert_eq!(
+      trace.render(),
+      "######....
+.#...#....
+.#...#####
+.#...##...
+.#...##...
+.#...##...
+.#..####..
+########..
+.#######..
+.#...#.#.."
+    );
+  }
+
+  #[test]
+  fn finds_best_entry_point() {
+    let contraption = parse(EXAMPLE);
+
+    assert_eq!(
+      best_entry_point(&contraption),
+      Some((
+        Beam {
+          position: Position::new(3, -1),
+          direction: Direction::Down,
+        },
+        51
+      ))
+    );
+  }
+
+  #[test]
+  fn detects_loops() {
+    let contraption = parse("/.\\\n|..\n\\./");
+    let trace = simulate(
+      &contraption,
+      &[Beam {
+        position: Position::new(-1, 1),
+        direction: Direction::Right,
+      }],
+    );
+
+    assert_eq!(trace.energised_count(), 8);
+    assert_eq!(trace.loops.len(), 2);
+    assert_eq!(
+      trace.directions(Position::new(0, 1)),
+      vec![Direction::Up, Direction::Down, Direction::Right]
+    );
+    assert!(trace.directions(Position::new(-1, 1)).is_empty());
+    assert!(trace.directions(Position::new(1, 3)).is_empty());
+
+    let straight = simulate(
+      &parse(".."),
+      &[Beam {
+        position: Position::new(-1, 0),
+        direction: Direction::Right,
+      }],
+    );
+    assert!(straight.loops.is_empty());
+  }
+
+  #[test]
+  fn graph_matches_simulation() {
+    for contraption in [parse(EXAMPLE), parse("/.\\\n|..\n\\./")] {
+      let graph = BeamGraph::new(&contraption);
+
+      for beam in edge_beams(&contraption) {
+        assert_eq!(
+          graph.energised(&contraption, beam),
+          solve(&contraption, beam)
+        );
+      }
+      assert_eq!(
+        graph.best_entry_point(&contraption),
+        best_entry_point(&contraption)
+      );
+    }
+  }
+
+  #[test]
+  fn graph_matches_simulation_on_large_grids() {
+    let mut rng = Rng::new();
+    let tiles = ['.', '.', '.', '.', '.', '.', '-', '|', '/', '\\'];
+
+    for _ in 0..3 {
+      let grid = (0..60)
+        .map(|_| {
+          (0..60)
+            .map(|_| tiles[rng.next(tiles.len() as u64) as usize])
+            .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+      let contraption = parse(&grid);
+      let graph = BeamGraph::new(&contraption);
+
+      for beam in edge_beams(&contraption) {
+        assert_eq!(
+          graph.energised(&contraption, beam),
+          solve(&contraption, beam)
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn condenses_long_chains() {
+    // Deep enough to overflow the stack if the search recursed
+    let n = 1_000_000;
+    let mut edges = (0..n).map(|node| vec![node + 1]).collect::<Vec<_>>();
+    edges[n - 1] = vec![n / 2];
+
+    let mut tarjan = Tarjan::new(&edges);
+    tarjan.connect(0);
+
+    // The back half is one loop, every node before it is on its own
+    assert_eq!(tarjan.found.len(), n / 2 + 1);
+    assert_eq!(tarjan.found[0].len(), n / 2);
+    assert!((n / 2..n).all(|node| tarjan.components[node] == 0));
+    assert_eq!(tarjan.components[0], n / 2);
+  }
+
+  // Times the per-edge solve loop against the beam graph on the real input.
+  // cargo test --release day_16 -- --ignored --nocapture
+  #[test]
+  #[ignore]
+  fn bench_edge_scan() {
+    let contraption = read_input().unwrap();
+
+    let start = Instant::now();
+    let slow = best_entry_point(&contraption);
+    let slow_time = start.elapsed();
+
+    let start = Instant::now();
+    let fast = BeamGraph::new(&contraption).best_entry_point(&contraption);
+    let fast_time = start.elapsed();
+
+    println!("solve loop: {:?}, beam graph: {:?}", slow_time, fast_time);
+    assert_eq!(slow.map(|(_, n)| n), fast.map(|(_, n)| n));
+  }
+
+  #[test]
+  fn simulates_custom_elements() {
+    let optics = Optics::standard()
+      .register('#', Absorber)
+      .register('*', Prism)
+      .register(
+        'N',
+        OneWayMirror {
+          mirror: Mirror::Slash,
+          reflecting: [Direction::Right, Direction::Down],
+        },
+      )
+      .register_teleport('A');
+    let parse = |input: &str| optics.parse(input.lines().map(String::from));
+    let from_left = |y| Beam::new(Position::new(-1, y), Direction::Right);
+
+    let absorbed = parse("..#..").unwrap();
+    assert_eq!(simulate(&absorbed, &[from_left(0)]).energised_count(), 3);
+
+    let teleported = parse("A..\n..A").unwrap();
+    let trace = simulate(&teleported, &[from_left(0)]);
+    assert_eq!(trace.render(), "#..\n..#");
+
+    let split = parse(".*.\n...").unwrap();
+    let trace = simulate(&split, &[from_left(0)]);
+    assert_eq!(trace.render(), "###\n.#.");
+
+    // Reflected from the front, passed through from the back
+    let one_way = parse("...\n.N.\n...").unwrap();
+    let trace = simulate(&one_way, &[from_left(1)]);
+    assert_eq!(trace.render(), ".#.\n##.\n...");
+    let trace = simulate(&one_way, &[Beam::new(Position::new(3, 1), Direction::Left)]);
+    assert_eq!(trace.render(), "...\n###\n...");
+
+    for contraption in [absorbed, teleported, split, one_way] {
+      let graph = BeamGraph::new(&contraption);
+      for beam in edge_beams(&contraption) {
+        assert_eq!(
+          graph.energised(&contraption, beam),
+          solve(&contraption, beam)
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn rejects_unknown_tiles() {
+    assert!(Optics::standard()
+      .parse([".?.".to_string()].into_iter())
+      .is_err());
+    assert!(Optics::standard()
+      .register_teleport('A')
+      .parse(["A..".to_string()].into_iter())
+      .is_err());
+  }
+}
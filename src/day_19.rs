@@ -1,13 +1,18 @@
-use std::{collections::HashMap, fmt::Debug, io};
+use std::{
+  collections::{BTreeMap, HashMap},
+  fmt::{Debug, Display},
+  io,
+};
 
-use crate::fs::read_day;
+use crate::{checked, fs::read_day, interval_set::IntervalSet};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 enum Category {
   X, // extremely cool looking
   M, // Musical
   A, // Aerodynamic
   S, // Shiny
+  Other(String),
 }
 
 impl Category {
@@ -16,19 +21,32 @@ impl Category {
   }
 }
 
-impl From<char> for Category {
-  fn from(c: char) -> Self {
-    match c {
-      'x' => Category::X,
-      'm' => Category::M,
-      'a' => Category::A,
-      's' => Category::S,
-      _ => panic!("Invalid category"),
+impl From<&str> for Category {
+  fn from(s: &str) -> Self {
+    match s {
+      "x" => Category::X,
+      "m" => Category::M,
+      "a" => Category::A,
+      "s" => Category::S,
+      "" => panic!("Missing category"),
+      _ => Category::Other(s.to_string()),
+    }
+  }
+}
+
+impl Display for Category {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Category::X => write!(f, "x"),
+      Category::M => write!(f, "m"),
+      Category::A => write!(f, "a"),
+      Category::S => write!(f, "s"),
+      Category::Other(name) => write!(f, "{}", name),
     }
   }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 enum Decision {
   Accept,
   Reject,
@@ -66,69 +84,224 @@ impl From<String> for Outcome {
   }
 }
 
-enum Rule {
-  GreaterThan(Category, u64, Outcome),
-  LessThan(Category, u64, Outcome),
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Operator {
+  Greater,
+  AtLeast,
+  Less,
+  AtMost,
+  Equal,
+  NotEqual,
 }
 
-impl Rule {
-  fn get_category(&self) -> Category {
+impl Operator {
+  // Longest first so ">=" isn't read as ">"
+  const ALL: [Operator; 6] = [
+    Operator::AtLeast,
+    Operator::AtMost,
+    Operator::Equal,
+    Operator::NotEqual,
+    Operator::Greater,
+    Operator::Less,
+  ];
+
+  fn symbol(self) -> &'static str {
     match self {
-      Rule::GreaterThan(c, _, _) => *c,
-      Rule::LessThan(c, _, _) => *c,
+      Operator::Greater => ">",
+      Operator::AtLeast => ">=",
+      Operator::Less => "<",
+      Operator::AtMost => "<=",
+      Operator::Equal => "==",
+      Operator::NotEqual => "!=",
     }
   }
 
-  fn get_outcome(&self) -> &Outcome {
+  fn holds(self, lhs: u64, rhs: u64) -> bool {
     match self {
-      Rule::GreaterThan(_, _, o) => o,
-      Rule::LessThan(_, _, o) => o,
+      Operator::Greater => lhs > rhs,
+      Operator::AtLeast => lhs >= rhs,
+      Operator::Less => lhs < rhs,
+      Operator::AtMost => lhs <= rhs,
+      Operator::Equal => lhs == rhs,
+      Operator::NotEqual => lhs != rhs,
+    }
+  }
+
+  // The values v for which `v op rhs` holds
+  fn values(self, rhs: u64) -> IntervalSet<u64> {
+    let everything = |set: IntervalSet<u64>| set.complement(0, u64::MAX);
+    match self {
+      Operator::Greater => everything(IntervalSet::range(0, rhs)),
+      Operator::AtLeast => IntervalSet::range(rhs, u64::MAX),
+      Operator::Less => everything(IntervalSet::range(rhs, u64::MAX)),
+      Operator::AtMost => IntervalSet::range(0, rhs),
+      Operator::Equal => IntervalSet::range(rhs, rhs),
+      Operator::NotEqual => everything(IntervalSet::range(rhs, rhs)),
     }
   }
 }
 
-impl Debug for Rule {
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Comparison {
+  category: Category,
+  operator: Operator,
+  value: u64,
+}
+
+impl Comparison {
+  fn matches(&self, part: &Part) -> bool {
+    self.operator.holds(part.get(&self.category), self.value)
+  }
+
+  // The pieces of the box that match and the pieces that don't
+  fn split(&self, b: &RatingBox) -> (Vec<RatingBox>, Vec<RatingBox>) {
+    let range = b.get(&self.category);
+    let range = IntervalSet::range(range.min_inclusive, range.max_inclusive);
+    let matching = self.operator.values(self.value).intersection(&range);
+    let not_matching = range.difference(&matching);
+
+    let pieces = |values: IntervalSet<u64>| {
+      values
+        .iter()
+        .map(|(lo, hi)| b.with(self.category.clone(), Range::new(lo, hi)))
+        .collect::<Vec<_>>()
+    };
+    (pieces(matching), pieces(not_matching))
+  }
+}
+
+impl From<&str> for Comparison {
+  // a>=1548
+  fn from(s: &str) -> Self {
+    let at = s.find(['<', '>', '=', '!']).expect("Missing operator");
+    let (category, rest) = s.split_at(at);
+    let operator = Operator::ALL
+      .into_iter()
+      .find(|op| rest.starts_with(op.symbol()))
+      .expect("Invalid operator");
+    let value = rest[operator.symbol().len()..]
+      .parse::<u64>()
+      .expect("Invalid value");
+    Comparison {
+      category: Category::from(category),
+      operator,
+      value,
+    }
+  }
+}
+
+impl Display for Comparison {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    match self {
-      Rule::GreaterThan(c, v, o) => write!(f, "{:?} > {} -> {:?}", c, v, o),
-      Rule::LessThan(c, v, o) => write!(f, "{:?} < {} -> {:?}", c, v, o),
+    write!(
+      f,
+      "{}{}{}",
+      self.category,
+      self.operator.symbol(),
+      self.value
+    )
+  }
+}
+
+// Comparisons that all have to hold
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Condition {
+  all_of: Vec<Comparison>,
+}
+
+impl Condition {
+  fn matches(&self, part: &Part) -> bool {
+    self
+      .all_of
+      .iter()
+      .all(|comparison| comparison.matches(part))
+  }
+
+  // Disjoint pieces of the box that match and that don't. Whatever fails one
+  // comparison doesn't match, and only what passes is checked against the next.
+  fn split(&self, b: &RatingBox) -> (Vec<RatingBox>, Vec<RatingBox>) {
+    let mut matching = vec![b.clone()];
+    let mut not_matching = vec![];
+
+    for comparison in &self.all_of {
+      let mut passed = vec![];
+      for b in &matching {
+        let (pass, fail) = comparison.split(b);
+        passed.extend(pass);
+        not_matching.extend(fail);
+      }
+      matching = passed;
+    }
+
+    (matching, not_matching)
+  }
+}
+
+impl From<&str> for Condition {
+  // x>10&m!=3
+  fn from(s: &str) -> Self {
+    Condition {
+      all_of: s.split('&').map(Comparison::from).collect(),
     }
   }
 }
 
+impl Display for Condition {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    for (i, comparison) in self.all_of.iter().enumerate() {
+      if i > 0 {
+        write!(f, "&")?;
+      }
+      write!(f, "{}", comparison)?;
+    }
+    Ok(())
+  }
+}
+
+struct Rule {
+  condition: Condition,
+  outcome: Outcome,
+}
+
+impl Rule {
+  fn condition(&self) -> &Condition {
+    &self.condition
+  }
+
+  fn get_outcome(&self) -> &Outcome {
+    &self.outcome
+  }
+}
+
+impl Debug for Rule {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{} -> {:?}", self.condition, self.outcome)
+  }
+}
+
 impl From<String> for Rule {
-  // m>1548:next_workflow
+  // m>1548&x<=20:next_workflow
   fn from(s: String) -> Self {
-    let mut chars = s.chars();
-    let category = chars.next().unwrap().into();
-    let op = chars.next().unwrap();
-    let (value, outcome) = chars.as_str().split_once(':').unwrap();
-    let value = value.parse::<u64>().unwrap();
-    let outcome = Outcome::from(outcome.to_string());
-    match op {
-      '>' => Rule::GreaterThan(category, value, outcome),
-      '<' => Rule::LessThan(category, value, outcome),
-      _ => panic!("Invalid operator"),
+    let (condition, outcome) = s.split_once(':').unwrap();
+    Rule {
+      condition: Condition::from(condition),
+      outcome: Outcome::from(outcome.to_string()),
     }
   }
 }
 
+// Ratings a part doesn't list count as 0
 #[derive(Debug)]
 struct Part {
-  x: u64,
-  m: u64,
-  a: u64,
-  s: u64,
+  ratings: BTreeMap<Category, u64>,
 }
 
 impl Part {
-  fn get_category(&self, category: Category) -> u64 {
-    match category {
-      Category::X => self.x,
-      Category::M => self.m,
-      Category::A => self.a,
-      Category::S => self.s,
-    }
+  fn get(&self, category: &Category) -> u64 {
+    self.ratings.get(category).copied().unwrap_or(0)
+  }
+
+  fn total(&self) -> u64 {
+    self.ratings.values().sum()
   }
 }
 
@@ -136,28 +309,14 @@ impl From<String> for Part {
   // {x=787,m=2655,a=1222,s=2876}
   fn from(s: String) -> Self {
     let trimmed = s.trim_start_matches('{').trim_end_matches('}');
-    let mut parts = trimmed.split(',').collect::<Vec<&str>>();
-    let x = parts
-      .remove(0)
-      .trim_start_matches("x=")
-      .parse::<u64>()
-      .unwrap();
-    let m = parts
-      .remove(0)
-      .trim_start_matches("m=")
-      .parse::<u64>()
-      .unwrap();
-    let a = parts
-      .remove(0)
-      .trim_start_matches("a=")
-      .parse::<u64>()
-      .unwrap();
-    let s = parts
-      .remove(0)
-      .trim_start_matches("s=")
-      .parse::<u64>()
-      .unwrap();
-    Part { x, m, a, s }
+    let ratings = trimmed
+      .split(',')
+      .map(|rating| {
+        let (category, value) = rating.split_once('=').unwrap();
+        (Category::from(category), value.parse::<u64>().unwrap())
+      })
+      .collect();
+    Part { ratings }
   }
 }
 
@@ -170,21 +329,12 @@ struct Workflow {
 
 impl Workflow {
   fn outcome_of(&self, part: &Part) -> Outcome {
-    for rule in &self.rules {
-      match rule {
-        Rule::GreaterThan(category, value, outcome) => {
-          if part.get_category(*category) > *value {
-            return outcome.clone();
-          }
-        }
-        Rule::LessThan(category, value, outcome) => {
-          if part.get_category(*category) < *value {
-            return outcome.clone();
-          }
-        }
-      }
-    }
-    self.fallback.clone()
+    self
+      .rules
+      .iter()
+      .find(|rule| rule.condition.matches(part))
+      .map_or(&self.fallback, |rule| &rule.outcome)
+      .clone()
   }
 }
 
@@ -252,21 +402,22 @@ fn decision_for_part(workflows: &HashMap<String, Workflow>, part: &Part) -> Deci
 
 pub fn part_1() -> io::Result<u64> {
   let (workflows, parts) = parse_input()?;
-  let workflows = workflows
-    .into_iter()
-    .map(|w| (w.name.clone(), w))
-    .collect::<HashMap<String, Workflow>>();
+  let mut program = Program::compile(&workflows_by_name(workflows))?;
+  program.optimise();
 
   let sum = parts
     .iter()
     .map(|p| {
-      let decision = decision_for_part(&workflows, p);
-      match decision {
-        Decision::Accept => p.x + p.m + p.a + p.s,
+      let decision = program.decide(p).ok_or(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Part is stuck in a cycle",
+      ))?;
+      Ok(match decision {
+        Decision::Accept => p.total(),
         Decision::Reject => 0,
-      }
+      })
     })
-    .sum::<u64>();
+    .sum::<io::Result<u64>>()?;
 
   Ok(sum)
 }
@@ -288,6 +439,26 @@ impl Range {
   fn non_empty(&self) -> bool {
     self.min_inclusive <= self.max_inclusive
   }
+
+  // u128 so that [0, u64::MAX] still has a length
+  fn len(&self) -> u128 {
+    if self.non_empty() {
+      (self.max_inclusive - self.min_inclusive) as u128 + 1
+    } else {
+      0
+    }
+  }
+
+  fn contains(&self, value: u64) -> bool {
+    self.min_inclusive <= value && value <= self.max_inclusive
+  }
+
+  fn intersect(&self, other: &Range) -> Range {
+    Range::new(
+      self.min_inclusive.max(other.min_inclusive),
+      self.max_inclusive.min(other.max_inclusive),
+    )
+  }
 }
 
 impl Debug for Range {
@@ -296,101 +467,753 @@ impl Debug for Range {
   }
 }
 
-fn count_accepted_ranges(
-  ranges: &HashMap<Category, Range>,
+// A range of values for every category, i.e. a box in rating space. Categories
+// that haven't been narrowed down yet span the whole of `bounds`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct RatingBox {
+  ranges: BTreeMap<Category, Range>,
+  bounds: Range,
+}
+
+impl RatingBox {
+  fn new(bounds: Range) -> Self {
+    Self {
+      ranges: BTreeMap::new(),
+      bounds,
+    }
+  }
+
+  fn get(&self, category: &Category) -> Range {
+    self.ranges.get(category).copied().unwrap_or(self.bounds)
+  }
+
+  // Ranges equal to the bounds aren't stored, so equal boxes compare equal
+  fn with(&self, category: Category, range: Range) -> Self {
+    let mut result = self.clone();
+    if range == self.bounds {
+      result.ranges.remove(&category);
+    } else {
+      result.ranges.insert(category, range);
+    }
+    result
+  }
+
+  fn non_empty(&self) -> bool {
+    self.bounds.non_empty() && self.ranges.values().all(|range| range.non_empty())
+  }
+
+  // Number of points in the box, counting only the given categories
+  fn volume(&self, categories: &[Category]) -> io::Result<u128> {
+    checked::product(categories.iter().map(|category| self.get(category).len()))
+  }
+
+  // Ratings the part doesn't list count as 0, as they do in the workflows, so
+  // such a part is only inside boxes whose bounds reach down to 0
+  fn contains(&self, part: &Part) -> bool {
+    Category::all()
+      .iter()
+      .chain(self.ranges.keys())
+      .chain(part.ratings.keys())
+      .all(|category| self.get(category).contains(part.get(category)))
+  }
+
+  fn intersect(&self, other: &RatingBox) -> Option<RatingBox> {
+    let mut result = RatingBox::new(self.bounds.intersect(&other.bounds));
+    for category in self.ranges.keys().chain(other.ranges.keys()) {
+      let range = self.get(category).intersect(&other.get(category));
+      result = result.with(category.clone(), range);
+    }
+    Some(result).filter(|b| b.non_empty())
+  }
+}
+
+// Pushes the box through the workflows and collects the disjoint boxes that end
+// up accepted.
+fn collect_accepted(
+  b: RatingBox,
   outcome: &Outcome,
   workflows: &HashMap<String, Workflow>,
-) -> u64 {
-  let mut ranges = ranges.to_owned();
-
+  accepted: &mut Vec<RatingBox>,
+) {
   match outcome {
-    Outcome::Terminal(Decision::Reject) => return 0,
-    Outcome::Terminal(Decision::Accept) => {
-      let mut product = 1;
-      for range in ranges.values() {
-        product *= range.max_inclusive - range.min_inclusive + 1;
-      }
-      return product;
-    }
+    Outcome::Terminal(Decision::Reject) => {}
+    Outcome::Terminal(Decision::Accept) => accepted.push(b),
     Outcome::NextWorkflow { name } => {
       let workflow = workflows.get(name).unwrap();
+      let mut remaining = vec![b];
 
-      let mut total = 0;
+      for rule in workflow.rules.iter() {
+        if remaining.is_empty() {
+          return;
+        }
 
-      let mut covered_all_cases = false;
+        let mut not_matching = vec![];
+        for b in &remaining {
+          let (matching, rest) = rule.condition().split(b);
+          for b in matching {
+            collect_accepted(b, rule.get_outcome(), workflows, accepted);
+          }
+          not_matching.extend(rest);
+        }
+        remaining = not_matching;
+      }
 
-      for rule in workflow.rules.iter() {
-        let category = rule.get_category();
-        let &Range {
-          min_inclusive,
-          max_inclusive,
-        } = ranges.get(&category).unwrap();
-
-        let (matching, not_matching) = match rule {
-          Rule::GreaterThan(_, value, _) => {
-            let matching_half = Range::new(*value + 1, max_inclusive);
-            let non_matching_half = Range::new(min_inclusive, *value);
-            (matching_half, non_matching_half)
+      for b in remaining {
+        collect_accepted(b, &workflow.fallback, workflows, accepted);
+      }
+    }
+  }
+}
+
+fn accepted_boxes(workflows: &HashMap<String, Workflow>, start: RatingBox) -> Vec<RatingBox> {
+  let mut accepted = vec![];
+  collect_accepted(
+    start,
+    &Outcome::NextWorkflow {
+      name: "in".to_string(),
+    },
+    workflows,
+    &mut accepted,
+  );
+  accepted
+}
+
+// Values of the category that no accepted box reaches, so any part with one of
+// them is rejected whatever its other ratings are.
+fn always_rejected(accepted: &[RatingBox], category: &Category, bounds: Range) -> Vec<Range> {
+  let reached = accepted
+    .iter()
+    .map(|b| b.get(category))
+    .map(|range| (range.min_inclusive, range.max_inclusive))
+    .collect::<IntervalSet<u64>>();
+
+  reached
+    .complement(bounds.min_inclusive, bounds.max_inclusive)
+    .iter()
+    .map(|(lo, hi)| Range::new(lo, hi))
+    .collect()
+}
+
+// Number of accepted parts with every rating within `bounds`. All boxes are
+// measured over x, m, a, s and every other category any of them narrows, so boxes
+// that only differ in a custom category aren't counted twice.
+fn accepted_combinations(workflows: &HashMap<String, Workflow>, bounds: Range) -> io::Result<u128> {
+  let accepted = accepted_boxes(workflows, RatingBox::new(bounds));
+
+  let mut categories = Category::all();
+  categories.extend(accepted.iter().flat_map(|b| b.ranges.keys().cloned()));
+  categories.sort();
+  categories.dedup();
+
+  let volumes = accepted
+    .iter()
+    .map(|b| b.volume(&categories))
+    .collect::<io::Result<Vec<_>>>()?;
+  checked::sum(volumes)
+}
+
+fn workflows_by_name(workflows: Vec<Workflow>) -> HashMap<String, Workflow> {
+  workflows
+    .into_iter()
+    .map(|w| (w.name.clone(), w))
+    .collect::<HashMap<String, Workflow>>()
+}
+
+pub fn part_2() -> io::Result<u64> {
+  let (workflows, _) = parse_input()?;
+  let workflows = workflows_by_name(workflows);
+
+  checked::narrow(accepted_combinations(&workflows, Range::new(1, 4000))?)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Target {
+  Workflow(usize),
+  Terminal(Decision),
+}
+
+#[derive(Debug, Clone)]
+struct CompiledWorkflow {
+  name: String,
+  rules: Vec<(Condition, Target)>,
+  fallback: Target,
+}
+
+// Workflows with every outcome resolved to an index, so evaluating a part never
+// has to look up a name.
+#[derive(Debug, Clone)]
+struct Program {
+  workflows: Vec<CompiledWorkflow>,
+  start: Target,
+}
+
+impl Program {
+  fn compile(workflows: &HashMap<String, Workflow>) -> io::Result<Program> {
+    // "in" first, the rest sorted so the output is stable
+    let mut names = workflows.keys().cloned().collect::<Vec<_>>();
+    names.sort_by_key(|name| (name != "in", name.clone()));
+    let indices = names
+      .iter()
+      .enumerate()
+      .map(|(index, name)| (name.clone(), index))
+      .collect::<HashMap<_, _>>();
+
+    let target = |outcome: &Outcome| match outcome {
+      Outcome::Terminal(decision) => Ok(Target::Terminal(*decision)),
+      Outcome::NextWorkflow { name } => indices
+        .get(name)
+        .map(|&index| Target::Workflow(index))
+        .ok_or(io::Error::new(
+          io::ErrorKind::InvalidData,
+          format!("Unknown workflow {}", name),
+        )),
+    };
+
+    let compiled = names
+      .iter()
+      .map(|name| {
+        let workflow = &workflows[name];
+        Ok(CompiledWorkflow {
+          name: name.clone(),
+          rules: workflow
+            .rules
+            .iter()
+            .map(|rule| Ok((rule.condition().clone(), target(rule.get_outcome())?)))
+            .collect::<io::Result<Vec<_>>>()?,
+          fallback: target(&workflow.fallback)?,
+        })
+      })
+      .collect::<io::Result<Vec<_>>>()?;
+
+    let start = target(&Outcome::NextWorkflow {
+      name: "in".to_string(),
+    })?;
+
+    Ok(Program {
+      workflows: compiled,
+      start,
+    })
+  }
+
+  // None if the part goes round in circles
+  fn decide(&self, part: &Part) -> Option<Decision> {
+    let mut target = self.start;
+
+    for _ in 0..=self.workflows.len() {
+      let index = match target {
+        Target::Terminal(decision) => return Some(decision),
+        Target::Workflow(index) => index,
+      };
+
+      let workflow = &self.workflows[index];
+      target = workflow
+        .rules
+        .iter()
+        .find(|(condition, _)| condition.matches(part))
+        .map_or(workflow.fallback, |(_, target)| *target);
+    }
+
+    None
+  }
+
+  fn successors(&self, index: usize) -> Vec<usize> {
+    let workflow = &self.workflows[index];
+    workflow
+      .rules
+      .iter()
+      .map(|(_, target)| *target)
+      .chain([workflow.fallback])
+      .filter_map(|target| match target {
+        Target::Workflow(next) => Some(next),
+        Target::Terminal(_) => None,
+      })
+      .collect()
+  }
+
+  fn reachable_from(&self, target: Target) -> Vec<bool> {
+    let mut seen = vec![false; self.workflows.len()];
+    let mut stack = match target {
+      Target::Workflow(index) => vec![index],
+      Target::Terminal(_) => vec![],
+    };
+
+    while let Some(index) = stack.pop() {
+      if !seen[index] {
+        seen[index] = true;
+        stack.extend(self.successors(index));
+      }
+    }
+
+    seen
+  }
+
+  // Workflows no part can ever reach
+  fn unreachable(&self) -> Vec<&str> {
+    let reachable = self.reachable_from(self.start);
+    self
+      .workflows
+      .iter()
+      .zip(reachable)
+      .filter(|(_, reachable)| !reachable)
+      .map(|(workflow, _)| workflow.name.as_str())
+      .collect()
+  }
+
+  // Workflows that can send a part back to themselves
+  fn cycles(&self) -> Vec<&str> {
+    (0..self.workflows.len())
+      .filter(|&index| {
+        self
+          .successors(index)
+          .into_iter()
+          .any(|next| self.reachable_from(Target::Workflow(next))[index])
+      })
+      .map(|index| self.workflows[index].name.as_str())
+      .collect()
+  }
+
+  fn retarget(&mut self, map: impl Fn(Target) -> Target) {
+    for workflow in self.workflows.iter_mut() {
+      for (_, target) in workflow.rules.iter_mut() {
+        *target = map(*target);
+      }
+      workflow.fallback = map(workflow.fallback);
+    }
+    self.start = map(self.start);
+  }
+
+  // Drops rules that can never match or never make a difference, then replaces
+  // workflows that always lead to the same place, until nothing changes.
+  fn optimise(&mut self) {
+    // Every pass that changes anything removes a rule or a workflow, which bounds
+    // the passes even when workflows that only pass parts on form a cycle
+    let passes = self.workflows.len()
+      + self
+        .workflows
+        .iter()
+        .map(|workflow| workflow.rules.len())
+        .sum::<usize>();
+
+    for _ in 0..=passes {
+      let mut changed = false;
+
+      for workflow in self.workflows.iter_mut() {
+        let before = workflow.rules.len();
+        let mut remaining = vec![RatingBox::new(Range::new(0, u64::MAX))];
+        let mut absorbed = false;
+        let mut rules = vec![];
+
+        for (condition, target) in &workflow.rules {
+          if remaining.is_empty() {
+            break;
+          }
+          let mut matching = vec![];
+          let mut not_matching = vec![];
+          for b in &remaining {
+            let (pass, fail) = condition.split(b);
+            matching.extend(pass);
+            not_matching.extend(fail);
           }
-          Rule::LessThan(_, value, _) => {
-            let matching_half = Range::new(min_inclusive, *value - 1);
-            let non_matching_half = Range::new(*value, max_inclusive);
-            (matching_half, non_matching_half)
+
+          if matching.is_empty() {
+            continue;
           }
-        };
+          if not_matching.is_empty() {
+            // Everything left matches, so this is the new fallback
+            workflow.fallback = *target;
+            absorbed = true;
+            break;
+          }
+          rules.push((condition.clone(), *target));
+          remaining = not_matching;
+        }
 
-        if matching.non_empty() {
-          let mut new_ranges = ranges.clone();
-          new_ranges.insert(category, matching);
-          let count = count_accepted_ranges(&new_ranges, rule.get_outcome(), workflows);
-          total += count;
+        while rules
+          .last()
+          .is_some_and(|(_, target)| *target == workflow.fallback)
+        {
+          rules.pop();
         }
 
-        if not_matching.non_empty() {
-          ranges = ranges.clone();
-          ranges.insert(category, not_matching);
+        changed |= rules.len() != before || absorbed;
+        workflow.rules = rules;
+      }
+
+      // Workflows with nothing but a fallback, and workflows identical to an
+      // earlier one, are replaced by where they lead
+      let mut replacements = HashMap::new();
+      let mut seen = HashMap::new();
+      for (index, workflow) in self.workflows.iter().enumerate() {
+        if workflow.rules.is_empty() && workflow.fallback != Target::Workflow(index) {
+          replacements.insert(index, workflow.fallback);
+        } else if let Some(&first) = seen.get(&(&workflow.rules, workflow.fallback)) {
+          replacements.insert(index, Target::Workflow(first));
         } else {
-          covered_all_cases = true;
-          break;
+          seen.insert((&workflow.rules, workflow.fallback), index);
+        }
+      }
+
+      let redirected = |mut target: Target| {
+        // Replacements can chain, but never for longer than there are workflows
+        for _ in 0..replacements.len() {
+          match target {
+            Target::Workflow(index) if replacements.contains_key(&index) => {
+              target = replacements[&index];
+            }
+            _ => break,
+          }
         }
+        target
+      };
+      let before = (self.start, self.workflows.clone());
+      self.retarget(redirected);
+      changed |= self.start != before.0
+        || self
+          .workflows
+          .iter()
+          .zip(before.1.iter())
+          .any(|(a, b)| a.rules != b.rules || a.fallback != b.fallback);
+
+      if !changed {
+        break;
+      }
+    }
+
+    self.remove_unreachable();
+  }
+
+  fn remove_unreachable(&mut self) {
+    let reachable = self.reachable_from(self.start);
+    let mut indices = vec![None; self.workflows.len()];
+    let mut workflows = vec![];
+
+    for (index, workflow) in std::mem::take(&mut self.workflows).into_iter().enumerate() {
+      if reachable[index] {
+        indices[index] = Some(workflows.len());
+        workflows.push(workflow);
       }
+    }
+
+    self.workflows = workflows;
+    self.retarget(|target| match target {
+      Target::Workflow(index) => Target::Workflow(indices[index].unwrap()),
+      terminal => terminal,
+    });
+
+    // Whatever we start in has to be called "in" for the puzzle format
+    if let Target::Workflow(start) = self.start {
+      self.workflows[start].name = "in".to_string();
+    }
+  }
 
-      if covered_all_cases {
-        return total;
-      } else {
-        return total + count_accepted_ranges(&ranges, &workflow.fallback, workflows);
+  fn target_name(&self, target: Target) -> String {
+    match target {
+      Target::Workflow(index) => self.workflows[index].name.clone(),
+      Target::Terminal(Decision::Accept) => "A".to_string(),
+      Target::Terminal(Decision::Reject) => "R".to_string(),
+    }
+  }
+}
+
+impl Display for Program {
+  // Workflows in the puzzle format, one per line
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    if let Target::Terminal(_) = self.start {
+      return writeln!(f, "in{{{}}}", self.target_name(self.start));
+    }
+
+    for workflow in &self.workflows {
+      write!(f, "{}{{", workflow.name)?;
+      for (condition, target) in &workflow.rules {
+        write!(f, "{}:{},", condition, self.target_name(*target))?;
       }
+      writeln!(f, "{}}}", self.target_name(workflow.fallback))?;
     }
+
+    Ok(())
   }
 }
 
-pub fn part_2() -> io::Result<u64> {
-  let (workflows, _) = parse_input()?;
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const EXAMPLE: &str = "px{a<2006:qkq,m>2090:A,rfg}
+pv{a>1716:R,A}
+lnx{m>1548:A,A}
+rfg{s<537:gd,x>2440:R,A}
+qs{s>3448:A,lnx}
+qkq{x<1416:A,crn}
+crn{x>2662:A,R}
+in{s<1351:px,qqz}
+qqz{s>2770:qs,m<1801:hdj,R}
+gd{a>3333:R,R}
+hdj{m>838:A,pv}
+
+{x=787,m=2655,a=1222,s=2876}
+{x=1679,m=44,a=2067,s=496}
+{x=2036,m=264,a=79,s=2244}
+{x=2461,m=1339,a=466,s=291}
+{x=2127,m=1623,a=2188,s=1013}";
+
+  fn parse(input: &str) -> (HashMap<String, Workflow>, Vec<Part>) {
+    let (workflows, parts) = input.split_once("\n\n").unwrap();
+    let workflows = workflows
+      .lines()
+      .map(|line| Workflow::from(line.to_string()))
+      .collect();
+    let parts = parts
+      .lines()
+      .map(|line| Part::from(line.to_string()))
+      .collect();
+    (workflows_by_name(workflows), parts)
+  }
 
-  let mut ranges = HashMap::<Category, Range>::new();
+  #[test]
+  fn splits_boxes() {
+    let b = RatingBox::new(Range::new(1, 10));
+    let split = |condition: &str| Condition::from(condition).split(&b);
 
-  for category in Category::all() {
-    ranges.insert(
-      category,
-      Range {
-        min_inclusive: 1,
-        max_inclusive: 4000,
-      },
+    assert_eq!(
+      split("m<4"),
+      (
+        vec![b.with(Category::M, Range::new(1, 3))],
+        vec![b.with(Category::M, Range::new(4, 10))]
+      )
     );
+    assert_eq!(split("m>=1"), (vec![b.clone()], vec![]));
+    assert_eq!(split("m>10"), (vec![], vec![b.clone()]));
+    assert_eq!(
+      split("m!=4"),
+      (
+        vec![
+          b.with(Category::M, Range::new(1, 3)),
+          b.with(Category::M, Range::new(5, 10))
+        ],
+        vec![b.with(Category::M, Range::new(4, 4))]
+      )
+    );
+    assert_eq!(
+      split("m<=4&x==2"),
+      (
+        vec![b
+          .with(Category::M, Range::new(1, 4))
+          .with(Category::X, Range::new(2, 2))],
+        vec![
+          b.with(Category::M, Range::new(5, 10)),
+          b.with(Category::M, Range::new(1, 4))
+            .with(Category::X, Range::new(1, 1)),
+          b.with(Category::M, Range::new(1, 4))
+            .with(Category::X, Range::new(3, 10)),
+        ]
+      )
+    );
+    assert_eq!(b.volume(&Category::all()).unwrap(), 10_000);
+    assert!(RatingBox::new(Range::new(0, u64::MAX))
+      .volume(&Category::all())
+      .is_err());
+
+    let other = RatingBox::new(Range::new(5, 20)).with(Category::S, Range::new(10, 10));
+    assert_eq!(
+      b.intersect(&other)
+        .unwrap()
+        .volume(&Category::all())
+        .unwrap(),
+      6 * 6 * 6
+    );
+    assert_eq!(b.intersect(&RatingBox::new(Range::new(11, 20))), None);
   }
 
-  let workflows = workflows
-    .into_iter()
-    .map(|w| (w.name.clone(), w))
-    .collect::<HashMap<String, Workflow>>();
+  #[test]
+  fn evaluates_richer_rules() {
+    let (workflows, parts) = parse(
+      "in{x>=3&weight!=5:A,x==1:b,R}
+b{weight<=2:R,A}
 
-  let count = count_accepted_ranges(
-    &ranges,
-    &Outcome::NextWorkflow {
-      name: "in".to_string(),
-    },
-    &workflows,
-  );
+{x=3,weight=4}
+{x=3,weight=5}
+{x=1,weight=2}
+{x=1,weight=3,colour=7}",
+    );
+    let decisions = parts
+      .iter()
+      .map(|part| decision_for_part(&workflows, part))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      decisions,
+      vec![
+        Decision::Accept,
+        Decision::Reject,
+        Decision::Reject,
+        Decision::Accept
+      ]
+    );
+
+    let mut program = Program::compile(&workflows).unwrap();
+    program.optimise();
+    assert_eq!(
+      program.to_string(),
+      "in{x>=3&weight!=5:A,x==1:b,R}\nb{weight<=2:R,A}\n"
+    );
+    for (part, decision) in parts.iter().zip(decisions) {
+      assert_eq!(program.decide(part), Some(decision));
+    }
+
+    // Counting boxes agrees with checking every part in a small space
+    let categories = [Category::X, Category::from("weight")];
+    let accepted = accepted_boxes(&workflows, RatingBox::new(Range::new(1, 10)));
+    let expected = (1..=10)
+      .flat_map(|x| (1..=10).map(move |weight| format!("{{x={},weight={}}}", x, weight)))
+      .filter(|part| decision_for_part(&workflows, &Part::from(part.clone())) == Decision::Accept)
+      .count();
+    assert_eq!(
+      accepted
+        .iter()
+        .map(|b| b.volume(&categories).unwrap())
+        .sum::<u128>(),
+      expected as u128
+    );
+  }
+
+  #[test]
+  fn partitions_accepted_parts() {
+    let (workflows, parts) = parse(EXAMPLE);
+    let accepted = accepted_boxes(&workflows, RatingBox::new(Range::new(1, 4000)));
+
+    assert_eq!(
+      accepted
+        .iter()
+        .map(|b| b.volume(&Category::all()).unwrap())
+        .sum::<u128>(),
+      167409079868000
+    );
+
+    // The boxes are disjoint, and agree with running each part through the workflows
+    for (i, a) in accepted.iter().enumerate() {
+      for b in &accepted[i + 1..] {
+        assert_eq!(a.intersect(b), None);
+      }
+    }
+    for part in &parts {
+      let decision = decision_for_part(&workflows, part);
+      let in_partition = accepted.iter().any(|b| b.contains(part));
+      assert_eq!(matches!(decision, Decision::Accept), in_partition);
+    }
+
+    assert_eq!(
+      accepted_combinations(&workflows, Range::new(1, 4000)).unwrap(),
+      167409079868000
+    );
+    assert_eq!(
+      always_rejected(&accepted, &Category::X, Range::new(1, 4000)),
+      vec![]
+    );
+    let one_rule = parse("in{x>10:R,A}\n\n").0;
+    let accepted = accepted_boxes(&one_rule, RatingBox::new(Range::new(1, 4000)));
+    assert_eq!(
+      always_rejected(&accepted, &Category::X, Range::new(1, 4000)),
+      vec![Range::new(11, 4000)]
+    );
+  }
+
+  #[test]
+  fn counts_custom_categories() {
+    // Both sides are accepted, so every weight is
+    let split = parse("in{weight<=2:A,A}\n\n").0;
+    assert_eq!(
+      accepted_combinations(&split, Range::new(1, 4000)).unwrap(),
+      4000u128.pow(5)
+    );
+
+    let narrowed = parse("in{weight<=2:A,R}\n\n").0;
+    assert_eq!(
+      accepted_combinations(&narrowed, Range::new(1, 4000)).unwrap(),
+      2 * 4000u128.pow(4)
+    );
+  }
 
-  Ok(count)
+  #[test]
+  fn treats_missing_ratings_as_zero() {
+    let (workflows, _) = parse(EXAMPLE);
+    let parts = [
+      "{x=787,m=2655,a=1222}",
+      "{m=44,a=2067,s=496}",
+      "{x=2127,s=291}",
+      "{x=1}",
+    ]
+    .map(|part| Part::from(part.to_string()));
+
+    // Bounds that include 0 agree with running the parts through the workflows
+    let with_zero = accepted_boxes(&workflows, RatingBox::new(Range::new(0, 4000)));
+    for part in &parts {
+      let decision = decision_for_part(&workflows, part);
+      let in_partition = with_zero.iter().any(|b| b.contains(part));
+      assert_eq!(decision == Decision::Accept, in_partition);
+    }
+    assert!(parts
+      .iter()
+      .any(|part| decision_for_part(&workflows, part) == Decision::Accept));
+
+    // Otherwise a missing rating is out of bounds
+    let accepted = accepted_boxes(&workflows, RatingBox::new(Range::new(1, 4000)));
+    assert!(parts
+      .iter()
+      .all(|part| accepted.iter().all(|b| !b.contains(part))));
+  }
+
+  #[test]
+  fn compiles_and_optimises() {
+    let (workflows, parts) = parse(EXAMPLE);
+    let mut program = Program::compile(&workflows).unwrap();
+    assert!(program.unreachable().is_empty());
+    assert!(program.cycles().is_empty());
+
+    program.optimise();
+    for part in &parts {
+      assert_eq!(
+        program.decide(part),
+        Some(decision_for_part(&workflows, part))
+      );
+    }
+    // gd, lnx and with it qs always lead to the same place
+    assert_eq!(
+      program.to_string(),
+      "in{s<1351:px,qqz}
+crn{x>2662:A,R}
+hdj{m>838:A,pv}
+pv{a>1716:R,A}
+px{a<2006:qkq,m>2090:A,rfg}
+qkq{x<1416:A,crn}
+qqz{s>2770:A,m<1801:hdj,R}
+rfg{s<537:R,x>2440:R,A}
+"
+    );
+  }
+
+  #[test]
+  fn removes_dead_rules_and_duplicates() {
+    let (workflows, _) = parse(
+      "in{x>10:a,x>20:c,b}
+a{m<5:A,m<3:R,R}
+b{m<5:A,R}
+c{s>0:A,R}
+d{x<5:d,R}
+
+",
+    );
+    let mut program = Program::compile(&workflows).unwrap();
+    assert_eq!(program.unreachable(), vec!["d"]);
+    assert_eq!(program.cycles(), vec!["d"]);
+
+    program.optimise();
+    assert_eq!(program.to_string(), "in{m<5:A,R}\n");
+
+    let (workflows, _) = parse("in{x>10:R,a}\na{x<20:R,R}\n\n");
+    let mut program = Program::compile(&workflows).unwrap();
+    program.optimise();
+    assert_eq!(program.to_string(), "in{R}\n");
+
+    let (workflows, _) = parse("in{x>10:R,nope}\n\n");
+    assert!(Program::compile(&workflows).is_err());
+  }
 }
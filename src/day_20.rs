@@ -3,7 +3,10 @@ use std::{
   io, vec,
 };
 
-use crate::fs::read_day;
+use crate::{
+  checked::{lcm_all, narrow},
+  fs::read_day,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Signal {
@@ -163,30 +166,6 @@ pub fn part_1() -> io::Result<i64> {
   Ok(low_count * high_count)
 }
 
-fn lcm(first: usize, second: usize) -> usize {
-  first * second / gcd(first, second)
-}
-
-fn gcd(first: usize, second: usize) -> usize {
-  let mut max = first;
-  let mut min = second;
-  if min > max {
-    let val = max;
-    max = min;
-    min = val;
-  }
-
-  loop {
-    let res = max % min;
-    if res == 0 {
-      return min;
-    }
-
-    max = min;
-    min = res;
-  }
-}
-
 pub fn part_2() -> io::Result<usize> {
   let (broadcastees, mut modules) = parse_input()?;
 
@@ -296,9 +275,7 @@ pub fn part_2() -> io::Result<usize> {
     }
   }
 
-  let result = cycle_lengths
-    .values()
-    .fold(1, |acc, x| lcm(acc as usize, *x as usize));
+  let result = lcm_all(cycle_lengths.values().map(|&x| x as u128))?;
 
-  Ok(result)
+  narrow(result)
 }
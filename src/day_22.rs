@@ -1,5 +1,5 @@
 use std::{
-  collections::{HashMap, HashSet},
+  collections::{HashMap, HashSet, VecDeque},
   fmt::Debug,
   io,
   str::FromStr,
@@ -7,7 +7,8 @@ use std::{
 
 use crate::fs::read_day;
 
-struct Position {
+#[derive(Clone)]
+pub struct Position {
   x: i32,
   y: i32,
   z: i32,
@@ -52,7 +53,8 @@ impl FromStr for Position {
   }
 }
 
-struct Brick {
+#[derive(Clone)]
+pub struct Brick {
   start: Position,
   end: Position,
 }
@@ -62,13 +64,6 @@ impl Brick {
     Self { start, end }
   }
 
-  // Birds-eye view overlap
-  fn overlaps_xy(&self, other: &Self) -> bool {
-    let overlaps_x = self.start.x.max(other.start.x) <= self.end.x.min(other.end.x);
-    let overlaps_y = self.start.y.max(other.start.y) <= self.end.y.min(other.end.y);
-    overlaps_x && overlaps_y
-  }
-
   fn height(&self) -> usize {
     (self.end.z - self.start.z + 1) as usize
   }
@@ -110,69 +105,525 @@ fn parse_input() -> io::Result<Vec<Brick>> {
   Ok(result)
 }
 
-fn make_bricks_fall(bricks: &mut Vec<Brick>) {
-  bricks.sort_by(|a, b| a.end.z.cmp(&b.end.z));
+struct SupportMaps {
+  key_supports_values: HashMap<usize, HashSet<usize>>,
+  key_is_supported_by_values: HashMap<usize, HashSet<usize>>,
+}
+
+impl SupportMaps {
+  // Immediate dominator of every brick in the support graph, rooted at the ground.
+  // None means nothing but the ground is guaranteed to hold the brick up. Relies on
+  // supports having lower indices, so a brick's supports are always done first.
+  fn dominators(&self) -> Vec<Option<usize>> {
+    let count = self.key_is_supported_by_values.len();
+    let mut dominators = vec![None; count];
+    let mut depths = vec![0; count];
+
+    for index in 0..count {
+      let dominator = self.key_is_supported_by_values[&index]
+        .iter()
+        .map(|&below| Some(below))
+        .reduce(|a, b| common_dominator(a, b, &dominators, &depths))
+        .flatten();
 
-  for index in 0..bricks.len() {
-    let mut max_z = 1;
-    let brick = &bricks[index];
-    for check_index in 0..index {
-      let check = &bricks[check_index];
-      if brick.overlaps_xy(check) {
-        max_z = max_z.max(check.end.z + 1);
+      dominators[index] = dominator;
+      depths[index] = dominator.map_or(0, |dominator| depths[dominator]) + 1;
+    }
+
+    dominators
+  }
+
+  // How many other bricks fall when each brick is disintegrated. That is exactly
+  // the bricks it dominates, so it is its subtree size in the dominator tree.
+  pub fn chain_reactions(&self) -> Vec<usize> {
+    let dominators = self.dominators();
+    let mut subtree_sizes = vec![1; dominators.len()];
+
+    for index in (0..dominators.len()).rev() {
+      if let Some(dominator) = dominators[index] {
+        subtree_sizes[dominator] += subtree_sizes[index];
       }
     }
 
-    bricks[index].end.z -= brick.start.z - max_z;
-    bricks[index].start.z = max_z;
+    subtree_sizes.into_iter().map(|size| size - 1).collect()
+  }
+}
+
+fn common_dominator(
+  mut a: Option<usize>,
+  mut b: Option<usize>,
+  dominators: &[Option<usize>],
+  depths: &[usize],
+) -> Option<usize> {
+  let depth = |node: Option<usize>| node.map_or(0, |node| depths[node]);
+
+  while a != b {
+    if depth(a) >= depth(b) {
+      a = a.and_then(|node| dominators[node]);
+    } else {
+      b = b.and_then(|node| dominators[node]);
+    }
   }
 
-  bricks.sort_by(|a, b| a.start.z.cmp(&b.start.z));
+  a
 }
 
-struct SupportMaps {
-  key_supports_values: HashMap<usize, HashSet<usize>>,
-  key_is_supported_by_values: HashMap<usize, HashSet<usize>>,
+// The highest settled brick over every column, or None for bare ground
+struct HeightMap {
+  width: usize,
+  depth: usize,
+  tops: Vec<Option<(i32, usize)>>,
 }
 
-impl From<&Vec<Brick>> for SupportMaps {
-  fn from(bricks: &Vec<Brick>) -> Self {
-    let mut key_supports_values = HashMap::new();
-    let mut key_is_supported_by_values = HashMap::new();
+impl HeightMap {
+  fn new() -> Self {
+    Self {
+      width: 0,
+      depth: 0,
+      tops: vec![],
+    }
+  }
 
-    for index in 0..bricks.len() {
-      key_supports_values.insert(index, HashSet::<usize>::new());
-      key_is_supported_by_values.insert(index, HashSet::<usize>::new());
+  // Grows the map when the brick sticks out past its edges
+  fn fit(&mut self, brick: &Brick) {
+    let width = self.width.max(brick.end.x as usize + 1);
+    let depth = self.depth.max(brick.end.y as usize + 1);
+    if (width, depth) == (self.width, self.depth) {
+      return;
     }
 
-    for (top_index, top) in bricks.iter().enumerate() {
-      for (index, brick) in bricks.iter().take(top_index).enumerate() {
-        // `brick` supports `top` iff they overlap on the xy plane and `brick`s end z coordinate is exactly 1 less than `top`s start z coordinate
-        if brick.overlaps_xy(top) && brick.end.z + 1 == top.start.z {
-          key_supports_values
-            .get_mut(&index)
-            .unwrap()
-            .insert(top_index);
+    let mut tops = vec![None; width * depth];
+    for y in 0..self.depth {
+      for x in 0..self.width {
+        tops[y * width + x] = self.tops[y * self.width + x];
+      }
+    }
 
-          key_is_supported_by_values
-            .get_mut(&top_index)
-            .unwrap()
-            .insert(index);
-        }
+    self.width = width;
+    self.depth = depth;
+    self.tops = tops;
+  }
+
+  fn columns(&self, brick: &Brick) -> Vec<usize> {
+    (brick.start.x..=brick.end.x)
+      .flat_map(|x| {
+        (brick.start.y..=brick.end.y).map(move |y| y as usize * self.width + x as usize)
+      })
+      .collect()
+  }
+
+  // The brick has to be above everything under its footprint
+  fn place(&mut self, brick: &Brick, index: usize) {
+    self.fit(brick);
+    for column in self.columns(brick) {
+      self.tops[column] = Some((brick.end.z, index));
+    }
+  }
+}
+
+// A settled stack of bricks. Bricks are numbered in the order they settled, so
+// supports always come first.
+pub struct BrickStack {
+  bricks: Vec<Brick>,
+  support_maps: SupportMaps,
+  height_map: HeightMap,
+}
+
+impl BrickStack {
+  pub fn new(mut bricks: Vec<Brick>) -> io::Result<Self> {
+    bricks.sort_by(|a, b| a.start.z.cmp(&b.start.z));
+
+    let mut stack = Self {
+      bricks: vec![],
+      support_maps: SupportMaps {
+        key_supports_values: HashMap::new(),
+        key_is_supported_by_values: HashMap::new(),
+      },
+      height_map: HeightMap::new(),
+    };
+
+    for brick in bricks {
+      stack.add(brick)?;
+    }
+
+    Ok(stack)
+  }
+
+  pub fn bricks(&self) -> &[Brick] {
+    &self.bricks
+  }
+
+  // Lets a brick fall from where it is onto the stack. It comes to rest one above
+  // the highest top under its footprint, and the bricks owning that top support
+  // it. A brick starting at or below that top would have to pass through another
+  // brick, so it is rejected.
+  pub fn add(&mut self, mut brick: Brick) -> io::Result<usize> {
+    let index = self.bricks.len();
+    self.height_map.fit(&brick);
+
+    let columns = self.height_map.columns(&brick);
+    let tops = columns
+      .iter()
+      .filter_map(|&column| self.height_map.tops[column])
+      .collect::<Vec<_>>();
+    let resting_on = tops.iter().map(|&(z, _)| z).max().unwrap_or(0);
+    if resting_on >= brick.start.z {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{:?} starts below a brick in its column", brick),
+      ));
+    }
+
+    let supported_by = tops
+      .iter()
+      .filter(|&&(z, _)| z == resting_on)
+      .map(|&(_, below)| below)
+      .collect::<HashSet<_>>();
+
+    for &below in &supported_by {
+      self
+        .support_maps
+        .key_supports_values
+        .get_mut(&below)
+        .unwrap()
+        .insert(index);
+    }
+    self
+      .support_maps
+      .key_supports_values
+      .insert(index, HashSet::new());
+    self
+      .support_maps
+      .key_is_supported_by_values
+      .insert(index, supported_by);
+
+    brick.end.z -= brick.start.z - (resting_on + 1);
+    brick.start.z = resting_on + 1;
+
+    self.height_map.place(&brick, index);
+    self.bricks.push(brick);
+    Ok(index)
+  }
+
+  // The bricks that would fall if all of `removed` were disintegrated at once. A
+  // brick stays up while it lies on the ground or on at least one brick that stays.
+  pub fn falling_without(&self, removed: &HashSet<usize>) -> HashSet<usize> {
+    let mut falling = HashSet::new();
+
+    for index in 0..self.bricks.len() {
+      if removed.contains(&index) {
+        continue;
+      }
+
+      let supported_by = &self.support_maps.key_is_supported_by_values[&index];
+      if !supported_by.is_empty()
+        && supported_by
+          .iter()
+          .all(|below| removed.contains(below) || falling.contains(below))
+      {
+        falling.insert(index);
+      }
+    }
+
+    falling
+  }
+
+  // Disintegrates the bricks and lets everything above settle again. Returns the
+  // bricks that fell, numbered as before the removal. The remaining bricks are
+  // renumbered afterwards.
+  //
+  // Only bricks numbered after the first removed one can rest on it, so the ones
+  // before it keep their places and supports. The rest are dropped again from
+  // where they are, and the height map is rebuilt from the bricks that stayed.
+  pub fn remove(&mut self, removed: &HashSet<usize>) -> io::Result<HashSet<usize>> {
+    let falling = self.falling_without(removed);
+    let Some(&first) = removed.iter().min() else {
+      return Ok(falling);
+    };
+    let first = first.min(self.bricks.len());
+
+    let later = self.bricks.split_off(first);
+    let maps = &mut self.support_maps;
+    for index in first..first + later.len() {
+      maps.key_supports_values.remove(&index);
+      maps.key_is_supported_by_values.remove(&index);
+    }
+    for above in maps.key_supports_values.values_mut() {
+      above.retain(|&index| index < first);
+    }
+
+    self.height_map = HeightMap::new();
+    for (index, brick) in self.bricks.iter().enumerate() {
+      self.height_map.place(brick, index);
+    }
+
+    for (index, brick) in (first..).zip(later) {
+      if !removed.contains(&index) {
+        self.add(brick)?;
+      }
+    }
+
+    Ok(falling)
+  }
+
+  // How many other bricks fall when each brick is disintegrated on its own,
+  // numbered like `bricks()`
+  pub fn chain_reactions(&self) -> Vec<usize> {
+    self.support_maps.chain_reactions()
+  }
+
+  // Bricks that would take at least one other brick down with them
+  pub fn load_bearing(&self) -> Vec<usize> {
+    self
+      .chain_reactions()
+      .iter()
+      .enumerate()
+      .filter(|(_, &falling)| falling > 0)
+      .map(|(index, _)| index)
+      .collect()
+  }
+
+  // The smallest set of bricks whose removal makes `target` fall, or None when it
+  // lies on the ground. The target falls exactly when every path of supports from
+  // the ground up to it is cut, so this is a minimum vertex cut, found with max flow.
+  pub fn minimum_removal_to_drop(&self, target: usize) -> Option<HashSet<usize>> {
+    if self.support_maps.key_is_supported_by_values[&target].is_empty() {
+      return None;
+    }
+
+    // Brick i is split into 2i -> 2i + 1 so that cutting it costs 1
+    let ground = 2 * self.bricks.len();
+    let mut network = FlowNetwork::new(ground + 1);
+    for index in 0..self.bricks.len() {
+      network.add_edge(2 * index, 2 * index + 1, 1);
+
+      if self.support_maps.key_is_supported_by_values[&index].is_empty() {
+        network.add_edge(ground, 2 * index, usize::MAX);
+      }
+      for &above in &self.support_maps.key_supports_values[&index] {
+        network.add_edge(2 * index + 1, 2 * above, usize::MAX);
       }
     }
 
+    network.max_flow(ground, 2 * target);
+
+    let reachable = network.reachable(ground);
+    let cut = (0..self.bricks.len())
+      .filter(|&index| reachable[2 * index] && !reachable[2 * index + 1])
+      .collect();
+
+    Some(cut)
+  }
+
+  fn colours(&self, colouring: Colouring) -> Vec<[f32; 3]> {
+    let chain_reactions = self.chain_reactions();
+    let worst = chain_reactions.iter().copied().max().unwrap_or(0).max(1) as f32;
+
+    chain_reactions
+      .iter()
+      .map(|&falling| match colouring {
+        Colouring::Plain => [0.7, 0.7, 0.7],
+        Colouring::SafeToDisintegrate if falling == 0 => [0.2, 0.8, 0.2],
+        Colouring::SafeToDisintegrate => [0.8, 0.2, 0.2],
+        Colouring::ChainReaction => {
+          let heat = falling as f32 / worst;
+          [heat, 0.2, 1.0 - heat]
+        }
+      })
+      .collect()
+  }
+
+  // The settled stack as a Wavefront OBJ
+  pub fn to_obj(&self, colouring: Colouring) -> String {
+    render_obj(&self.bricks, &self.colours(colouring))
+  }
+}
+
+struct Edge {
+  to: usize,
+  capacity: usize,
+}
+
+struct FlowNetwork {
+  edges: Vec<Edge>,
+  adjacent: Vec<Vec<usize>>,
+}
+
+impl FlowNetwork {
+  fn new(nodes: usize) -> Self {
     Self {
-      key_supports_values,
-      key_is_supported_by_values,
+      edges: vec![],
+      adjacent: vec![vec![]; nodes],
     }
   }
+
+  // Edges are stored in pairs, so edge i ^ 1 is always the reverse of edge i
+  fn add_edge(&mut self, from: usize, to: usize, capacity: usize) {
+    self.adjacent[from].push(self.edges.len());
+    self.edges.push(Edge { to, capacity });
+    self.adjacent[to].push(self.edges.len());
+    self.edges.push(Edge {
+      to: from,
+      capacity: 0,
+    });
+  }
+
+  // Edmonds-Karp
+  fn max_flow(&mut self, source: usize, sink: usize) -> usize {
+    let mut total = 0;
+
+    loop {
+      let mut via = vec![None; self.adjacent.len()];
+      let mut queue = VecDeque::from([source]);
+
+      while let Some(node) = queue.pop_front() {
+        for &edge in &self.adjacent[node] {
+          let Edge { to, capacity } = self.edges[edge];
+          if capacity > 0 && to != source && via[to].is_none() {
+            via[to] = Some(edge);
+            queue.push_back(to);
+          }
+        }
+      }
+
+      if via[sink].is_none() {
+        return total;
+      }
+
+      let mut path = vec![];
+      let mut node = sink;
+      while let Some(edge) = via[node] {
+        path.push(edge);
+        node = self.edges[edge ^ 1].to;
+      }
+
+      let flow = path
+        .iter()
+        .map(|&edge| self.edges[edge].capacity)
+        .min()
+        .unwrap();
+      for edge in path {
+        self.edges[edge].capacity -= flow;
+        self.edges[edge ^ 1].capacity = self.edges[edge ^ 1].capacity.saturating_add(flow);
+      }
+      total += flow;
+    }
+  }
+
+  fn reachable(&self, source: usize) -> Vec<bool> {
+    let mut seen = vec![false; self.adjacent.len()];
+    seen[source] = true;
+    let mut stack = vec![source];
+
+    while let Some(node) = stack.pop() {
+      for &edge in &self.adjacent[node] {
+        let Edge { to, capacity } = self.edges[edge];
+        if capacity > 0 && !seen[to] {
+          seen[to] = true;
+          stack.push(to);
+        }
+      }
+    }
+
+    seen
+  }
+}
+
+fn make_bricks_fall(bricks: &mut Vec<Brick>) -> io::Result<SupportMaps> {
+  let stack = BrickStack::new(std::mem::take(bricks))?;
+  *bricks = stack.bricks;
+  Ok(stack.support_maps)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Colouring {
+  Plain,
+  // Green when nothing else falls without the brick, red otherwise
+  SafeToDisintegrate,
+  // From blue for no falling bricks to red for the worst brick in the stack
+  ChainReaction,
+}
+
+// Wavefront OBJ with one box per brick and colours as vertex colours. Z is up like
+// in the puzzle, and every cube spans a whole unit so neighbouring bricks touch.
+fn render_obj(bricks: &[Brick], colours: &[[f32; 3]]) -> String {
+  // Corner i has x, y and z taken from bits 0, 1 and 2, faces wind outwards
+  let faces = [
+    [0, 2, 3, 1],
+    [4, 5, 7, 6],
+    [0, 1, 5, 4],
+    [2, 6, 7, 3],
+    [0, 4, 6, 2],
+    [1, 3, 7, 5],
+  ];
+
+  let mut obj = String::new();
+
+  for (index, (brick, [r, g, b])) in bricks.iter().zip(colours).enumerate() {
+    obj.push_str(&format!("o brick_{}\n", index));
+
+    for corner in 0..8 {
+      let x = if corner & 1 == 0 {
+        brick.start.x
+      } else {
+        brick.end.x + 1
+      };
+      let y = if corner & 2 == 0 {
+        brick.start.y
+      } else {
+        brick.end.y + 1
+      };
+      let z = if corner & 4 == 0 {
+        brick.start.z
+      } else {
+        brick.end.z + 1
+      };
+      obj.push_str(&format!("v {} {} {} {} {} {}\n", x, y, z, r, g, b));
+    }
+
+    for face in faces {
+      let [a, b, c, d] = face.map(|corner| index * 8 + corner + 1);
+      obj.push_str(&format!("f {} {} {} {}\n", a, b, c, d));
+    }
+  }
+
+  obj
+}
+
+// The bricks where they are, before settling, coloured by what happens to each
+// once they have settled
+pub fn unsettled_to_obj(bricks: &[Brick], colouring: Colouring) -> io::Result<String> {
+  // The stack numbers bricks in this order
+  let mut order = (0..bricks.len()).collect::<Vec<_>>();
+  order.sort_by_key(|&index| bricks[index].start.z);
+
+  let stack = BrickStack::new(order.iter().map(|&index| bricks[index].clone()).collect())?;
+  let mut colours = vec![[0.0; 3]; bricks.len()];
+  for (&index, colour) in order.iter().zip(stack.colours(colouring)) {
+    colours[index] = colour;
+  }
+
+  Ok(render_obj(bricks, &colours))
+}
+
+// Writes the bricks to `path`, settled or as they are
+pub fn export_obj(
+  path: &str,
+  bricks: &[Brick],
+  settled: bool,
+  colouring: Colouring,
+) -> io::Result<()> {
+  let obj = if settled {
+    BrickStack::new(bricks.to_vec())?.to_obj(colouring)
+  } else {
+    unsettled_to_obj(bricks, colouring)?
+  };
+  std::fs::write(path, obj)
 }
 
 pub fn part_1() -> io::Result<usize> {
   let mut bricks = parse_input()?;
-  make_bricks_fall(&mut bricks);
-  let support_maps = SupportMaps::from(&bricks);
+  let support_maps = make_bricks_fall(&mut bricks)?;
 
   let mut total = 0;
 
@@ -199,42 +650,148 @@ pub fn part_1() -> io::Result<usize> {
   Ok(total)
 }
 
-fn find_all_collapsing(collapsing: &mut HashSet<usize>, support_maps: &SupportMaps, index: usize) {
-  let unvisited_supported_by_index = support_maps
-    .key_supports_values
-    .get(&index)
-    .unwrap()
-    .difference(collapsing)
-    .map(|x| *x)
-    .collect::<Vec<usize>>();
+pub fn part_2() -> io::Result<usize> {
+  let mut bricks = parse_input()?;
+  let support_maps = make_bricks_fall(&mut bricks)?;
 
-  for supported_by_index in unvisited_supported_by_index {
-    // if all bricks supporting `supported_by_index` are in `collapsing`, then `supported_by_index` is also collapsing
-    let all_bricks_supporting_collpasing = support_maps
-      .key_is_supported_by_values
-      .get(&supported_by_index)
-      .unwrap()
-      .is_subset(collapsing);
+  Ok(support_maps.chain_reactions().iter().sum())
+}
 
-    if all_bricks_supporting_collpasing {
-      collapsing.insert(supported_by_index);
-      find_all_collapsing(collapsing, support_maps, supported_by_index);
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const EXAMPLE: &str = "1,0,1~1,2,1
+0,0,2~2,0,2
+0,2,3~2,2,3
+0,0,4~0,2,4
+2,0,5~2,2,5
+0,1,6~2,1,6
+1,1,8~1,1,9";
+
+  fn parse(input: &str) -> Vec<Brick> {
+    input.lines().map(|line| line.parse().unwrap()).collect()
+  }
+
+  #[test]
+  fn settles_example() {
+    let mut bricks = parse(EXAMPLE);
+    let support_maps = make_bricks_fall(&mut bricks).unwrap();
+
+    assert_eq!(
+      bricks.iter().map(|brick| brick.start.z).collect::<Vec<_>>(),
+      vec![1, 2, 2, 3, 3, 4, 5]
+    );
+    assert_eq!(bricks[6].end.z, 6);
+    assert_eq!(
+      support_maps.key_is_supported_by_values[&3],
+      HashSet::from([1, 2])
+    );
+    assert_eq!(support_maps.key_supports_values[&0], HashSet::from([1, 2]));
+    assert!(support_maps.key_is_supported_by_values[&0].is_empty());
+  }
+
+  #[test]
+  fn counts_chain_reactions() {
+    let mut bricks = parse(EXAMPLE);
+    let support_maps = make_bricks_fall(&mut bricks).unwrap();
+
+    assert_eq!(support_maps.chain_reactions(), vec![6, 0, 0, 0, 0, 1, 0]);
+  }
+
+  #[test]
+  fn answers_what_if_queries() {
+    let mut stack = BrickStack::new(parse(EXAMPLE)).unwrap();
+
+    // A brings down the other six, F only G
+    assert_eq!(stack.chain_reactions(), vec![6, 0, 0, 0, 0, 1, 0]);
+    assert_eq!(stack.load_bearing(), vec![0, 5]);
+    assert_eq!(
+      stack.falling_without(&HashSet::from([1, 2])),
+      HashSet::from([3, 4, 5, 6])
+    );
+    for target in [3, 4, 6] {
+      let removal = stack.minimum_removal_to_drop(target).unwrap();
+      assert_eq!(removal.len(), 1);
+      assert!(stack.falling_without(&removal).contains(&target));
+    }
+    assert_eq!(stack.minimum_removal_to_drop(0), None);
+
+    let added = stack.add("1,1,20~1,3,20".parse().unwrap()).unwrap();
+    assert_eq!(stack.bricks()[added].start.z, 7);
+    assert_eq!(
+      stack.support_maps.key_is_supported_by_values[&added],
+      HashSet::from([6])
+    );
+
+    let fallen = stack.remove(&HashSet::from([5])).unwrap();
+    assert_eq!(fallen, HashSet::from([6, 7]));
+    assert_eq!(
+      stack
+        .bricks()
+        .iter()
+        .map(|brick| brick.start.z)
+        .collect::<Vec<_>>(),
+      vec![1, 2, 2, 3, 3, 2, 4]
+    );
+
+    // Same as settling what is left from scratch
+    let mut remaining = parse(EXAMPLE);
+    remaining.remove(5);
+    remaining.push("1,1,20~1,3,20".parse().unwrap());
+    let settled = BrickStack::new(remaining).unwrap();
+    assert_eq!(
+      format!("{:?}", stack.bricks()),
+      format!("{:?}", settled.bricks())
+    );
+    for index in 0..settled.bricks().len() {
+      assert_eq!(
+        stack.support_maps.key_is_supported_by_values[&index],
+        settled.support_maps.key_is_supported_by_values[&index]
+      );
+      assert_eq!(
+        stack.support_maps.key_supports_values[&index],
+        settled.support_maps.key_supports_values[&index]
+      );
     }
   }
-}
 
-pub fn part_2() -> io::Result<usize> {
-  let mut bricks = parse_input()?;
-  make_bricks_fall(&mut bricks);
-  let support_maps = SupportMaps::from(&bricks);
+  #[test]
+  fn rejects_bricks_under_overhangs() {
+    let snapshot = parse("0,0,1~0,0,3\n0,0,4~2,0,4\n2,0,1~2,0,1");
 
-  let mut total = 0;
-  for index in 0..bricks.len() {
-    let mut collapsing = HashSet::new();
-    collapsing.insert(index);
-    find_all_collapsing(&mut collapsing, &support_maps, index);
-    total += collapsing.len() - 1;
+    // Settling the snapshot sorts it by height first
+    let settled = BrickStack::new(snapshot.clone()).unwrap();
+    assert_eq!(settled.bricks()[2].start.z, 4);
+
+    let mut stack = BrickStack::new(snapshot[..2].to_vec()).unwrap();
+    assert!(stack.add(snapshot[2].clone()).is_err());
+    assert_eq!(stack.bricks().len(), 2);
   }
 
-  Ok(total)
+  #[test]
+  fn exports_obj() {
+    let stack = BrickStack::new(parse("0,0,1~1,0,1\n0,0,2~0,0,2")).unwrap();
+    let obj = stack.to_obj(Colouring::SafeToDisintegrate);
+
+    let lines = obj.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2 * (1 + 8 + 6));
+    assert_eq!(lines[0], "o brick_0");
+    assert_eq!(lines[1], "v 0 0 1 0.8 0.2 0.2");
+    assert_eq!(lines[8], "v 2 1 2 0.8 0.2 0.2");
+    assert_eq!(lines[9], "f 1 3 4 2");
+    assert_eq!(lines[16], "v 0 0 2 0.2 0.8 0.2");
+    assert_eq!(lines[29], "f 10 12 16 14");
+
+    // Unsettled bricks keep their order and height, and still get the colour of
+    // the brick they settle into
+    let unsettled = unsettled_to_obj(
+      &parse("0,0,5~0,0,5\n0,0,1~1,0,1"),
+      Colouring::SafeToDisintegrate,
+    )
+    .unwrap();
+    let lines = unsettled.lines().collect::<Vec<_>>();
+    assert_eq!(lines[1], "v 0 0 5 0.2 0.8 0.2");
+    assert_eq!(lines[16], "v 0 0 1 0.8 0.2 0.2");
+  }
 }
@@ -1,8 +1,12 @@
-use std::{fmt::Debug, io, result, str::FromStr};
+use std::{cmp::Ordering, fmt::Debug, io, result, str::FromStr};
 
 use nalgebra::{DMatrix, DVector};
 
-use crate::fs::read_day;
+use crate::{
+  checked::{checked, narrow},
+  fs::read_day,
+  newton::NewtonSolver,
+};
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
 struct Vec3 {
@@ -16,8 +20,8 @@ impl Vec3 {
     Vec3 { x, y, z }
   }
 
-  fn to_tuple(&self) -> (i64, i64, i64) {
-    (self.x, self.y, self.z)
+  fn to_vector(self) -> Vector {
+    [self.x as i128, self.y as i128, self.z as i128]
   }
 }
 
@@ -39,6 +43,165 @@ impl Debug for Vec3 {
   }
 }
 
+type Vector = [i128; 3];
+
+// Products of two input coordinates always fit in an i128, but products of those
+// don't, so every operation is checked
+fn components(values: [Option<i128>; 3], operation: &str) -> io::Result<Vector> {
+  let [x, y, z] = values;
+  Ok([
+    checked(x, operation)?,
+    checked(y, operation)?,
+    checked(z, operation)?,
+  ])
+}
+
+fn sub(a: Vector, b: Vector) -> io::Result<Vector> {
+  components(
+    [0, 1, 2].map(|axis| a[axis].checked_sub(b[axis])),
+    "Subtraction",
+  )
+}
+
+fn dot(a: Vector, b: Vector) -> io::Result<i128> {
+  (0..3).try_fold(0i128, |sum, axis| {
+    checked(
+      a[axis]
+        .checked_mul(b[axis])
+        .and_then(|product| sum.checked_add(product)),
+      "Dot product",
+    )
+  })
+}
+
+// a[i]·b[j] - a[j]·b[i]
+fn wedge(a: Vector, b: Vector, i: usize, j: usize) -> Option<i128> {
+  a[i].checked_mul(b[j])?.checked_sub(a[j].checked_mul(b[i])?)
+}
+
+fn cross(a: Vector, b: Vector) -> io::Result<Vector> {
+  components(
+    [wedge(a, b, 1, 2), wedge(a, b, 2, 0), wedge(a, b, 0, 1)],
+    "Cross product",
+  )
+}
+
+// pₐ + (t / denominator)·vₐ as a fraction
+fn point_at(
+  pa: Vector,
+  va: Vector,
+  t: i128,
+  denominator: i128,
+  axis: usize,
+) -> io::Result<Rational> {
+  let numerator = pa[axis]
+    .checked_mul(denominator)
+    .and_then(|scaled| scaled.checked_add(t.checked_mul(va[axis])?));
+  Ok(Rational::new(
+    checked(numerator, "Crossing point")?,
+    denominator,
+  ))
+}
+
+// A fraction in lowest terms with a positive denominator
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct Rational {
+  numerator: i128,
+  denominator: i128,
+}
+
+impl Rational {
+  fn new(numerator: i128, denominator: i128) -> Rational {
+    assert!(denominator != 0, "Division by zero");
+    let divisor = gcd(numerator, denominator) * denominator.signum();
+    Rational {
+      numerator: numerator / divisor,
+      denominator: denominator / divisor,
+    }
+  }
+
+  fn integer(value: i128) -> Rational {
+    Rational::new(value, 1)
+  }
+
+  fn is_negative(&self) -> bool {
+    self.numerator < 0
+  }
+}
+
+fn gcd(a: i128, b: i128) -> i128 {
+  let (mut a, mut b) = (a.abs(), b.abs());
+  while b != 0 {
+    (a, b) = (b, a % b);
+  }
+  a
+}
+
+impl Ord for Rational {
+  // Compares whole parts first and then the flipped remainders, like a continued
+  // fraction, so nothing is ever multiplied together and overflows
+  fn cmp(&self, other: &Rational) -> Ordering {
+    let whole = |r: &Rational| r.numerator.div_euclid(r.denominator);
+    let rest = |r: &Rational| r.numerator.rem_euclid(r.denominator);
+
+    match whole(self).cmp(&whole(other)) {
+      Ordering::Equal => {}
+      ordering => return ordering,
+    }
+    match (rest(self), rest(other)) {
+      (0, 0) => Ordering::Equal,
+      (0, _) => Ordering::Less,
+      (_, 0) => Ordering::Greater,
+      (a, b) => Rational::new(other.denominator, b).cmp(&Rational::new(self.denominator, a)),
+    }
+  }
+}
+
+impl PartialOrd for Rational {
+  fn partial_cmp(&self, other: &Rational) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Debug for Rational {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    if self.denominator == 1 {
+      write!(f, "{}", self.numerator)
+    } else {
+      write!(f, "{}/{}", self.numerator, self.denominator)
+    }
+  }
+}
+
+// How the paths of two stones meet. Each stone gets to the crossing point at its
+// own time, and a negative time means that stone was there in the past.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Crossing<const N: usize> {
+  Parallel,
+  Coincident,
+  // Only in 3D, where lines can miss without being parallel
+  Skew,
+  Point {
+    point: [Rational; N],
+    times: [Rational; 2],
+  },
+}
+
+impl<const N: usize> Crossing<N> {
+  fn in_future(&self) -> bool {
+    matches!(self, Crossing::Point { times, .. } if times.iter().all(|t| !t.is_negative()))
+  }
+
+  fn in_past(&self) -> bool {
+    matches!(self, Crossing::Point { times, .. } if times.iter().any(|t| t.is_negative()))
+  }
+
+  fn within(&self, lo: i128, hi: i128) -> bool {
+    let (lo, hi) = (Rational::integer(lo), Rational::integer(hi));
+    matches!(self, Crossing::Point { point, .. } if point.iter().all(|c| lo <= *c && *c <= hi))
+  }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 struct Hailstone {
   position: Vec3,
@@ -50,30 +213,63 @@ impl Hailstone {
     Hailstone { position, velocity }
   }
 
-  // x = xs + t * vx
-  // y = ys + t * vy
-  // Eliminating t
-  // (x - xs) / vx = (y - ys) / vy
-  // (x - xs) * vy = (y - ys) * vx
-  // Lets get this in the form of ax + by = c
-  // (vy) x - (vx) y = (vy * xs) - (vx * ys)
-  fn standard_form_xy(&self) -> Vec3 {
-    let a = self.velocity.y;
-    let b = -self.velocity.x;
-    let c = self.velocity.y * self.position.x - self.velocity.x * self.position.y;
-    Vec3::new(a, b, c)
-  }
-
-  fn intersection_xy(&self, other: &Hailstone) -> Option<(f64, f64)> {
-    let (a1, b1, c1) = self.standard_form_xy().to_tuple();
-    let (a2, b2, c2) = other.standard_form_xy().to_tuple();
-    let determinant = (a1 * b2 - a2 * b1) as f64;
-    if determinant == 0f64 {
-      return None;
+  // Where the paths cross in x and y, ignoring z. Solving
+  // pₐ + t·vₐ = p_b + s·v_b with 2D cross products gives
+  // t = (Δp × v_b) / (vₐ × v_b) and s = (Δp × vₐ) / (vₐ × v_b)
+  // Exact while |p|·|v|² stays below about 2^124, an error beyond that. Puzzle
+  // inputs are around 2^49 and 2^10.
+  fn crossing_xy(&self, other: &Hailstone) -> io::Result<Crossing<2>> {
+    let cross_xy = |a: Vector, b: Vector| checked(wedge(a, b, 0, 1), "Cross product");
+    let (pa, va) = (self.position.to_vector(), self.velocity.to_vector());
+    let (pb, vb) = (other.position.to_vector(), other.velocity.to_vector());
+    let offset = sub(pb, pa)?;
+
+    let denominator = cross_xy(va, vb)?;
+    if denominator == 0 {
+      return Ok(if cross_xy(offset, va)? == 0 {
+        Crossing::Coincident
+      } else {
+        Crossing::Parallel
+      });
+    }
+
+    let t = cross_xy(offset, vb)?;
+    let s = cross_xy(offset, va)?;
+    let [x, y] = [0, 1].map(|axis| point_at(pa, va, t, denominator, axis));
+    Ok(Crossing::Point {
+      point: [x?, y?],
+      times: [Rational::new(t, denominator), Rational::new(s, denominator)],
+    })
+  }
+
+  // The same in 3D, where n = vₐ × v_b. The lines only meet if Δp lies in the
+  // plane they span, and then t = ((Δp × v_b)·n) / |n|² and s = ((Δp × vₐ)·n) / |n|²
+  // Here the bound is |p|·|v|⁴, which the puzzle input still keeps well under.
+  fn crossing(&self, other: &Hailstone) -> io::Result<Crossing<3>> {
+    let (pa, va) = (self.position.to_vector(), self.velocity.to_vector());
+    let (pb, vb) = (other.position.to_vector(), other.velocity.to_vector());
+    let offset = sub(pb, pa)?;
+    let normal = cross(va, vb)?;
+
+    if normal == [0, 0, 0] {
+      return Ok(if cross(offset, va)? == [0, 0, 0] {
+        Crossing::Coincident
+      } else {
+        Crossing::Parallel
+      });
     }
-    let x = (b2 as f64 / determinant * c1 as f64) - (b1 as f64 / determinant * c2 as f64);
-    let y = (a1 as f64 / determinant * c2 as f64) - (a2 as f64 / determinant * c1 as f64);
-    Some((x, y))
+    if dot(offset, normal)? != 0 {
+      return Ok(Crossing::Skew);
+    }
+
+    let denominator = dot(normal, normal)?;
+    let t = dot(cross(offset, vb)?, normal)?;
+    let s = dot(cross(offset, va)?, normal)?;
+    let [x, y, z] = [0, 1, 2].map(|axis| point_at(pa, va, t, denominator, axis));
+    Ok(Crossing::Point {
+      point: [x?, y?, z?],
+      times: [Rational::new(t, denominator), Rational::new(s, denominator)],
+    })
   }
 }
 
@@ -93,169 +289,390 @@ fn parse_input() -> io::Result<Vec<Hailstone>> {
   Ok(hailstones)
 }
 
+// Pairs whose paths cross in x and y inside the square [lo, hi]², not counting
+// crossings either stone has already passed
+fn count_crossings_xy(hailstones: &[Hailstone], lo: i128, hi: i128) -> io::Result<usize> {
+  let mut count = 0;
+  for (index, hs1) in hailstones.iter().enumerate() {
+    for hs2 in &hailstones[index + 1..] {
+      let crossing = hs1.crossing_xy(hs2)?;
+      if crossing.in_future() && crossing.within(lo, hi) {
+        count += 1;
+      }
+    }
+  }
+  Ok(count)
+}
+
 pub fn part_1() -> io::Result<usize> {
   let hailstones = parse_input()?;
+  count_crossings_xy(&hailstones, 200000000000000, 400000000000000)
+}
 
-  let bounding_box = (200000000000000f64, 400000000000000f64);
-
-  let result = hailstones
-    .iter()
-    .enumerate()
-    .flat_map(|(index, hs1)| {
-      hailstones
-        .iter()
-        .skip(index + 1)
-        .filter_map(|hs2| {
-          hs1.intersection_xy(hs2).and_then(|(x, y)| {
-            // Check if its in the past by comparing the sign of the difference with velocity
-            let in_the_future = vec![hs1, hs2].iter().all(|hs| {
-              (x - hs.position.x as f64) * hs.velocity.x as f64 >= 0f64
-                && (y - hs.position.y as f64) * hs.velocity.y as f64 >= 0f64
-            });
-
-            let in_bounding_box = x >= bounding_box.0 as f64
-              && x <= bounding_box.1 as f64
-              && y >= bounding_box.0 as f64
-              && y <= bounding_box.1 as f64;
-
-            if in_the_future && in_bounding_box {
-              Some((x, y))
-            } else {
-              None
-            }
-          })
-        })
-        .collect::<Vec<_>>()
-    })
-    .count();
-
-  Ok(result)
+// Primes just below 2^61, 2^62 and 2^63, so a product of two residues fits in a u128
+const PRIMES: [u128; 3] = [
+  2305843009213693951,
+  4611686018427387847,
+  9223372036854775783,
+];
+
+fn mod_pow(mut base: u128, mut exponent: u128, modulus: u128) -> u128 {
+  let mut result = 1;
+  base %= modulus;
+  while exponent > 0 {
+    if exponent & 1 == 1 {
+      result = result * base % modulus;
+    }
+    base = base * base % modulus;
+    exponent >>= 1;
+  }
+  result
 }
 
-pub fn part_2() -> io::Result<i64> {
-  /*
-  Xr + t1 * Vxr - X1 - t1 Vx1 = 0
-  Yr + t1 * Vyr - Y1 - t1 Vy1 = 0
-  Zr + t1 * Vzr - Z1 - t1 Vz1 = 0
+// Gaussian elimination on an augmented matrix over the integers modulo a prime.
+// None if the matrix is singular there.
+fn solve_modulo(system: &[[i128; 7]; 6], modulus: u128) -> Option<[u128; 6]> {
+  let mut rows = system.map(|row| row.map(|value| value.rem_euclid(modulus as i128) as u128));
 
-  Xr + t2 * Vxr - X2 - t2 Vx2 = 0
-  Yr + t2 * Vyr - Y2 - t2 Vy2 = 0
-  Zr + t2 * Vzr - Z2 - t2 Vz2 = 0
+  for col in 0..6 {
+    let pivot = (col..6).find(|&row| rows[row][col] != 0)?;
+    rows.swap(col, pivot);
 
-  Xr + t3 * Vxr - X3 - t3 Vx3 = 0
-  Yr + t3 * Vyr - Y3 - t3 Vy3 = 0
-  Zr + t3 * Vzr - Z3 - t3 Vz3 = 0
+    // Fermat's little theorem gives the inverse
+    let inverse = mod_pow(rows[col][col], modulus - 2, modulus);
+    for value in rows[col].iter_mut() {
+      *value = *value * inverse % modulus;
+    }
 
-  let input be [Xr, Yr, Zr, Vxr, Vyr, Vzr, t1, t2, t3]
-   */
+    for row in 0..6 {
+      let factor = rows[row][col];
+      if row == col || factor == 0 {
+        continue;
+      }
+      let pivot_row = rows[col];
+      for (value, pivot_value) in rows[row].iter_mut().zip(pivot_row).skip(col) {
+        let subtrahend = factor * pivot_value % modulus;
+        *value = (*value + modulus - subtrahend) % modulus;
+      }
+    }
+  }
 
-  let hailstones = parse_input()?;
+  Some(rows.map(|row| row[6]))
+}
 
-  fn f(x: &DVector<f64>, hailstones: &Vec<Hailstone>) -> DVector<f64> {
-    let f0 =
-      // Xr + t1 * Vxr - X1 - t1 Vx1 = 0
-      x[0] + x[6] * x[3] - (hailstones[0].position.x as f64) - x[6] * (hailstones[0].velocity.x as f64);
+// Combines residues modulo two primes into the integer closest to zero
+fn chinese_remainder(a: u128, p: u128, b: u128, q: u128) -> i128 {
+  let inverse = mod_pow(p % q, q - 2, q);
+  let k = (b + q - a % q) % q * inverse % q;
+  // Both primes are below 2^63, so p * q fits
+  let x = a + p * k;
+  let modulus = p * q;
+  if x > modulus / 2 {
+    x as i128 - modulus as i128
+  } else {
+    x as i128
+  }
+}
 
-    let f1 =
-      // Yr + t1 * Vyr - Y1 - t1 Vy1 = 0
-      x[1] + x[6] * x[4] - (hailstones[0].position.y as f64) - x[6] * (hailstones[0].velocity.y as f64);
+// The rock hits stone i at time t when (p - pᵢ) = t(vᵢ - v), so (p - pᵢ) × (v - vᵢ) = 0.
+// Expanded, the only nonlinear term is p × v, which is the same for every stone.
+// Subtracting the equations of two stones cancels it:
+// p × (vⱼ - vᵢ) + (pⱼ - pᵢ) × v = pⱼ × vⱼ - pᵢ × vᵢ
+// Two pairs give six linear equations in the six unknowns.
+fn linear_system(stones: [&Hailstone; 3]) -> io::Result<[[i128; 7]; 6]> {
+  let mut system = [[0; 7]; 6];
+
+  for (pair, other) in [stones[1], stones[2]].into_iter().enumerate() {
+    let (pi, vi) = (
+      stones[0].position.to_vector(),
+      stones[0].velocity.to_vector(),
+    );
+    let (pj, vj) = (other.position.to_vector(), other.velocity.to_vector());
+    let a = sub(vj, vi)?;
+    let b = sub(pj, pi)?;
+    let rhs = sub(cross(pj, vj)?, cross(pi, vi)?)?;
+
+    // p × a and b × v written out as rows of coefficients for (p, v)
+    let rows = [
+      [0, a[2], -a[1], 0, -b[2], b[1]],
+      [-a[2], 0, a[0], b[2], 0, -b[0]],
+      [a[1], -a[0], 0, -b[1], b[0], 0],
+    ];
+    for (offset, row) in rows.iter().enumerate() {
+      let equation = &mut system[pair * 3 + offset];
+      equation[..6].copy_from_slice(row);
+      equation[6] = rhs[offset];
+    }
+  }
 
-    let f2 =
-      // Zr + t1 * Vzr - Z1 - t1 Vz1 = 0
-      x[2] + x[6] * x[5] - (hailstones[0].position.z as f64) - x[6] * (hailstones[0].velocity.z as f64);
+  Ok(system)
+}
 
-    let f3 =
-      // Xr + t2 * Vxr - X2 - t2 Vx2 = 0
-      x[0] + x[7] * x[3] - (hailstones[1].position.x as f64) - x[7] * (hailstones[1].velocity.x as f64);
+// Whether a rock thrown from p with velocity v at time 0 hits the stone at some
+// whole time t >= 0. Only a bogus candidate rock is far enough out to overflow.
+fn hits(position: Vector, velocity: Vector, stone: &Hailstone) -> bool {
+  let (Ok(offset), Ok(closing)) = (
+    sub(stone.position.to_vector(), position),
+    sub(velocity, stone.velocity.to_vector()),
+  ) else {
+    return false;
+  };
+
+  let Some(axis) = (0..3).find(|&axis| closing[axis] != 0) else {
+    return offset == [0, 0, 0];
+  };
+  if offset[axis] % closing[axis] != 0 {
+    return false;
+  }
 
-    let f4 =
-      // Yr + t2 * Vyr - Y2 - t2 Vy2 = 0
-      x[1] + x[7] * x[4] - (hailstones[1].position.y as f64) - x[7] * (hailstones[1].velocity.y as f64);
+  let t = offset[axis] / closing[axis];
+  t >= 0 && (0..3).all(|axis| t.checked_mul(closing[axis]) == Some(offset[axis]))
+}
 
-    let f5 =
-      // Zr + t2 * Vzr - Z2 - t2 Vz2 = 0
-      x[2] + x[7] * x[5] - (hailstones[1].position.z as f64) - x[7] * (hailstones[1].velocity.z as f64);
+// Why not solve the system exactly with Rational or fraction-free elimination:
+// three columns hold velocity differences (~2^10) and three hold position
+// differences (~2^49), so the 6×6 determinant and the minors elimination passes
+// through are around 2^180, far beyond an i128.
+//
+// Instead the system is solved modulo two primes and recombined. If the rock has
+// whole coordinates, they are the unique solution of the system, so modulo any
+// prime that doesn't divide the determinant the elimination finds exactly their
+// residues (a prime that does divide it makes the matrix singular and is skipped
+// for the next one). The Chinese remainder theorem then gives the one value in
+// (-pq/2, pq/2] with those residues, and since pq > 2^123 that's the coordinate
+// itself for anything smaller than 2^122.
+//
+// A rock with fractional or bigger coordinates gives meaningless residues, which
+// is why the candidate is only returned once it hits every stone.
+fn throw_rock(hailstones: &[Hailstone]) -> io::Result<(Vector, Vector)> {
+  for stones in hailstones.windows(3) {
+    let system = linear_system([&stones[0], &stones[1], &stones[2]])?;
+
+    let solutions = PRIMES
+      .iter()
+      .filter_map(|&prime| solve_modulo(&system, prime).map(|solution| (solution, prime)))
+      .take(2)
+      .collect::<Vec<_>>();
+    let [(a, p), (b, q)] = solutions[..] else {
+      // These three stones don't pin the rock down, try the next ones
+      continue;
+    };
+
+    let unknowns = (0..6)
+      .map(|i| chinese_remainder(a[i], p, b[i], q))
+      .collect::<Vec<_>>();
+    let position = [unknowns[0], unknowns[1], unknowns[2]];
+    let velocity = [unknowns[3], unknowns[4], unknowns[5]];
+
+    if hailstones
+      .iter()
+      .all(|stone| hits(position, velocity, stone))
+    {
+      return Ok((position, velocity));
+    }
+  }
 
-    let f6 =
-      // Xr + t3 * Vxr - X3 - t3 Vx3 = 0
-      x[0] + x[8] * x[3] - (hailstones[2].position.x as f64) - x[8] * (hailstones[2].velocity.x as f64);
+  Err(io::Error::new(
+    io::ErrorKind::NotFound,
+    "No rock with whole coordinates hits every hailstone",
+  ))
+}
 
-    let f7 =
-      // Yr + t3 * Vyr - Y3 - t3 Vy3 = 0
-      x[1] + x[8] * x[4] - (hailstones[2].position.y as f64) - x[8] * (hailstones[2].velocity.y as f64);
+pub fn part_2() -> io::Result<i64> {
+  let hailstones = parse_input()?;
+  let (position, _) = throw_rock(&hailstones)?;
+  narrow(position.iter().sum::<i128>())
+}
 
-    let f8 =
-      // Zr + t3 * Vzr - Z3 - t3 Vz3 = 0
-      x[2] + x[8] * x[5] - (hailstones[2].position.z as f64) - x[8] * (hailstones[2].velocity.z as f64);
+// Rock at p with velocity v hitting the first three stones at times t1, t2, t3:
+// p + tᵢ·v - pᵢ - tᵢ·vᵢ = 0 for each axis
+// Nine equations in x = [p, v, t1, t2, t3], solved numerically and then checked
+// against every stone after rounding.
+fn throw_rock_newton(hailstones: &[Hailstone], initial_guess: DVector<f64>) -> io::Result<Vector> {
+  let stones = hailstones.get(..3).ok_or(io::Error::new(
+    io::ErrorKind::InvalidData,
+    "Need at least three hailstones",
+  ))?;
+  let position = |i: usize| stones[i].position.to_vector().map(|c| c as f64);
+  let velocity = |i: usize| stones[i].velocity.to_vector().map(|c| c as f64);
+
+  let residual = |x: &DVector<f64>| {
+    DVector::from_fn(9, |row, _| {
+      let (i, axis) = (row / 3, row % 3);
+      let t = x[6 + i];
+      x[axis] + t * x[3 + axis] - position(i)[axis] - t * velocity(i)[axis]
+    })
+  };
 
-    DVector::from_vec(vec![f0, f1, f2, f3, f4, f5, f6, f7, f8])
+  let jacobian = |x: &DVector<f64>| {
+    let mut result = DMatrix::zeros(9, 9);
+    for row in 0..9 {
+      let (i, axis) = (row / 3, row % 3);
+      result[(row, axis)] = 1.0;
+      result[(row, 3 + axis)] = x[6 + i];
+      result[(row, 6 + i)] = x[3 + axis] - velocity(i)[axis];
+    }
+    result
+  };
+
+  // Coordinates are around 10^14, so the residual can't get much closer to zero
+  // than this in an f64. The guess is far enough off that full steps do better
+  // than damped ones here.
+  let root = NewtonSolver::new(residual)
+    .jacobian(jacobian)
+    .tolerance(1.0)
+    .max_iterations(1000)
+    .line_search(false)
+    .solve(initial_guess)?
+    .root;
+
+  let rock = [0, 1, 2].map(|axis| root[axis].round() as i128);
+  let velocity = [3, 4, 5].map(|axis| root[axis].round() as i128);
+  if hailstones.iter().all(|stone| hits(rock, velocity, stone)) {
+    Ok(rock)
+  } else {
+    Err(io::Error::new(
+      io::ErrorKind::InvalidData,
+      format!("Rounded root {:?} misses a hailstone", root.as_slice()),
+    ))
   }
+}
 
-  fn jacobian(x: &DVector<f64>, hailstones: &Vec<Hailstone>) -> DMatrix<f64> {
-    // get all zeroes 9x9
-    let mut result = DMatrix::zeros(9, 9);
+// The original numerical approach, kept for comparison
+pub fn part_2_newton() -> io::Result<i64> {
+  let hailstones = parse_input()?;
+  let initial_guess = DVector::from_vec(vec![
+    -1f64, -2f64, -3f64, -4f64, -5f64, -6f64, -7f64, -8f64, -9f64,
+  ]);
 
-    fn set(
-      matrix: &mut DMatrix<f64>,
-      x: &DVector<f64>,
-      row: usize,
-      col: usize,
-      index_of_t: usize,
-      d: f64,
-    ) {
-      matrix[(row, col)] = 1f64;
-      matrix[(row, col + 3)] = x[index_of_t];
-      matrix[(row, index_of_t)] = x[col + 3] - d
-    }
+  let position = throw_rock_newton(&hailstones, initial_guess)?;
+  narrow(position.iter().sum::<i128>())
+}
 
-    set(&mut result, x, 0, 0, 6, hailstones[0].velocity.x as f64);
-    set(&mut result, x, 1, 1, 6, hailstones[0].velocity.y as f64);
-    set(&mut result, x, 2, 2, 6, hailstones[0].velocity.z as f64);
+#[cfg(test)]
+mod tests {
+  use super::*;
 
-    set(&mut result, x, 3, 0, 7, hailstones[1].velocity.x as f64);
-    set(&mut result, x, 4, 1, 7, hailstones[1].velocity.y as f64);
-    set(&mut result, x, 5, 2, 7, hailstones[1].velocity.z as f64);
+  const EXAMPLE: &str = "19, 13, 30 @ -2,  1, -2
+18, 19, 22 @ -1, -1, -2
+20, 25, 34 @ -2, -2, -4
+12, 31, 28 @ -1, -2, -1
+20, 19, 15 @  1, -5, -3";
 
-    set(&mut result, x, 6, 0, 8, hailstones[2].velocity.x as f64);
-    set(&mut result, x, 7, 1, 8, hailstones[2].velocity.y as f64);
-    set(&mut result, x, 8, 2, 8, hailstones[2].velocity.z as f64);
+  fn parse(input: &str) -> Vec<Hailstone> {
+    input.lines().map(|line| line.parse().unwrap()).collect()
+  }
 
-    result
+  #[test]
+  fn throws_rock_exactly() {
+    let hailstones = parse(EXAMPLE);
+    assert_eq!(throw_rock(&hailstones).unwrap(), ([24, 13, 10], [-3, 1, 2]));
+
+    // The same stone three times says nothing about the rock, so the solver has
+    // to move on to the next ones
+    let repeated = [hailstones[0]; 3]
+      .into_iter()
+      .chain(hailstones.iter().copied())
+      .collect::<Vec<_>>();
+    let first = linear_system([&repeated[0], &repeated[1], &repeated[2]]).unwrap();
+    assert!(solve_modulo(&first, PRIMES[0]).is_none());
+    assert_eq!(throw_rock(&repeated).unwrap(), ([24, 13, 10], [-3, 1, 2]));
+
+    // A stone moving alongside the rock but off its line is never hit
+    let mut missed = hailstones.clone();
+    missed.push("0, 0, 0 @ -3, 1, 2".parse().unwrap());
+    assert!(throw_rock(&missed).is_err());
+  }
+
+  #[test]
+  fn throws_rock_numerically() {
+    let hailstones = parse(EXAMPLE);
+    let guess = DVector::from_fn(9, |i, _| -(i as f64) - 1.0);
+    assert_eq!(
+      throw_rock_newton(&hailstones, guess).unwrap(),
+      throw_rock(&hailstones).unwrap().0
+    );
   }
 
-  fn newtons_method(
-    initial_guess: DVector<f64>,
-    hailstones: &Vec<Hailstone>,
-    tolerance: f64,
-    max_iterations: usize,
-  ) -> DVector<f64> {
-    let mut x = initial_guess;
-
-    for _ in 0..max_iterations {
-      let j = jacobian(&x, hailstones);
-      let f_at_x = f(&x, &hailstones);
-      let l2_norm = f_at_x.norm();
-      if l2_norm < tolerance {
-        break;
+  #[test]
+  fn crosses_paths_exactly() {
+    let hailstones = parse(EXAMPLE);
+    assert_eq!(count_crossings_xy(&hailstones, 7, 27).unwrap(), 2);
+
+    let r = Rational::new;
+    assert_eq!(
+      hailstones[0].crossing_xy(&hailstones[1]).unwrap(),
+      Crossing::Point {
+        point: [r(43, 3), r(46, 3)],
+        times: [r(7, 3), r(11, 3)]
       }
-      let j_inv = j.try_inverse().expect("Jacobian is not invertible");
-      x = x - j_inv * f_at_x;
+    );
+    // Only the first stone has already been there
+    let past = hailstones[0].crossing_xy(&hailstones[4]).unwrap();
+    assert!(past.in_past() && !past.in_future());
+    assert_eq!(
+      hailstones[1].crossing_xy(&hailstones[2]).unwrap(),
+      Crossing::Parallel
+    );
+    let behind: Hailstone = "16, 17, 0 @ -1, -1, -2".parse().unwrap();
+    assert_eq!(
+      hailstones[1].crossing_xy(&behind).unwrap(),
+      Crossing::Coincident
+    );
+
+    // In 3D the example rock meets every stone, at the same time as the stone
+    let rock: Hailstone = "24, 13, 10 @ -3, 1, 2".parse().unwrap();
+    for (stone, time) in hailstones.iter().zip([5, 3, 4, 6, 1]) {
+      let t = Rational::integer(time);
+      assert!(matches!(
+        rock.crossing(stone).unwrap(),
+        Crossing::Point { times, .. } if times == [t, t]
+      ));
     }
-
-    x
+    assert_eq!(
+      hailstones[0].crossing(&hailstones[1]).unwrap(),
+      Crossing::Skew
+    );
+    assert_eq!(
+      hailstones[1].crossing(&hailstones[2]).unwrap(),
+      Crossing::Parallel
+    );
+    assert_eq!(hailstones[1].crossing(&behind).unwrap(), Crossing::Parallel);
+    let along: Hailstone = "17, 18, 20 @ 2, 2, 4".parse().unwrap();
+    assert_eq!(
+      hailstones[1].crossing(&along).unwrap(),
+      Crossing::Coincident
+    );
+
+    // Coordinates at the edge of an i64 are reported instead of wrapping
+    let max = i64::MAX;
+    let far: Hailstone = format!("{max}, {max}, {max} @ {max}, 1, 1")
+      .parse()
+      .unwrap();
+    let fast: Hailstone = format!("0, 0, 0 @ 1, {max}, 1").parse().unwrap();
+    assert!(far.crossing_xy(&fast).is_err());
+    assert!(far.crossing(&fast).is_err());
+    assert!(count_crossings_xy(&[far, fast], 0, 1).is_err());
   }
 
-  let initial_guess: DVector<f64> = DVector::from_vec(vec![
-    -1f64, -2f64, -3f64, -4f64, -5f64, -6f64, -7f64, -8f64, -9f64,
-  ]);
-
-  let tolerance = 1e-6;
-
-  let max_iterations = 1000;
-
-  let root: DVector<f64> = newtons_method(initial_guess, &hailstones, tolerance, max_iterations);
-
-  let position = Vec3::new(root[0] as i64, root[1] as i64, root[2] as i64);
+  #[test]
+  fn compares_rationals_without_overflow() {
+    let r = Rational::new;
+    assert_eq!(r(6, -4), r(-3, 2));
+    assert!(r(1, 3) < r(1, 2));
+    assert!(r(-1, 2) < r(-1, 3));
+    assert!(r(7, 3) > Rational::integer(2));
+    let huge = i128::MAX / 3;
+    assert!(r(huge, huge - 1) < r(huge - 1, huge - 2));
+    assert!(r(huge - 1, huge) < r(huge, huge + 1));
+  }
 
-  Ok(position.x + position.y + position.z)
+  #[test]
+  fn recombines_residues() {
+    let (p, q) = (PRIMES[0], PRIMES[1]);
+    for x in [0i128, 1, -1, 999782576459892, -(1 << 100), 1 << 120] {
+      let residue = |m: u128| x.rem_euclid(m as i128) as u128;
+      assert_eq!(chinese_remainder(residue(p), p, residue(q), q), x);
+    }
+  }
 }
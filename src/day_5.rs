@@ -1,6 +1,10 @@
-use std::{fmt::Debug, io, vec};
+use std::{
+  collections::{BTreeMap, HashMap, VecDeque},
+  fmt::Debug,
+  io, vec,
+};
 
-use crate::fs::read_day;
+use crate::{checked::checked, fs::read_day, interval_set::IntervalSet};
 
 #[derive(Clone, Copy, PartialEq)]
 pub struct Interval {
@@ -25,165 +29,140 @@ impl Interval {
       })
     }
   }
-}
 
-// Would include all numbers between left_interval and right_interval inclusive
-#[derive(Debug)]
-pub struct IntervalSearchTreeNode {
-  // Data for interval search
-  data: Interval,
-  interval: Interval,
-  max_to_the_right: u64,
-  // BST data
-  left: Box<IntervalSearchTree>,
-  right: Box<IntervalSearchTree>,
+  fn shifted(&self, shift: i128) -> Interval {
+    Interval {
+      left: (self.left as i128 + shift) as u64,
+      right: (self.right as i128 + shift) as u64,
+    }
+  }
 }
 
-pub struct IntervalSearchTree {
-  root: Option<IntervalSearchTreeNode>,
+// Maps every number in `interval` onto the same offset in `data`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mapping {
+  interval: Interval,
+  data: Interval,
 }
 
-impl Debug for IntervalSearchTree {
-  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    write!(f, "{}", self.string_repr())
-  }
+// Disjoint intervals keyed on their left bound. The BTreeMap keeps them balanced
+// and in order, so lookups are O(log n) and overlapping intervals can be walked
+// without looking at the rest.
+#[derive(Debug, Default)]
+pub struct IntervalMap {
+  mappings: BTreeMap<u64, Mapping>,
 }
 
-impl IntervalSearchTree {
+impl IntervalMap {
   pub fn new() -> Self {
-    Self { root: None }
+    Self {
+      mappings: BTreeMap::new(),
+    }
   }
 
-  pub fn string_repr(&self) -> String {
-    match self.root {
-      None => String::from("Leaf"),
-      Some(ref node) => {
-        let left = node.left.string_repr();
-        let right = node.right.string_repr();
-
-        format!(
-          "Node(L:{}, R:{}, M:{}, Left:{}, Right:{})",
-          node.interval.left, node.interval.right, node.max_to_the_right, left, right
-        )
-      }
+  pub fn insert(&mut self, left: u64, right: u64, data: Interval) -> io::Result<()> {
+    let interval = Interval { left, right };
+    if let Some(existing) = self.overlapping(&interval).next() {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{:?} overlaps {:?}", interval, existing.interval),
+      ));
     }
+
+    self.mappings.insert(left, Mapping { interval, data });
+    Ok(())
   }
 
-  pub fn insert(&mut self, left: u64, right: u64, data: Interval) {
-    match self.root {
-      None => {
-        self.root = Some(IntervalSearchTreeNode {
-          data,
-          interval: Interval { left, right },
-          max_to_the_right: right,
-          left: Box::new(IntervalSearchTree::new()),
-          right: Box::new(IntervalSearchTree::new()),
-        });
-      }
+  pub fn search(&self, point: u64) -> Option<&Mapping> {
+    self
+      .mappings
+      .range(..=point)
+      .next_back()
+      .map(|(_, mapping)| mapping)
+      .filter(|mapping| point <= mapping.interval.right)
+  }
 
-      Some(ref mut node) => {
-        // Binary search tree insertion with "left" as the key, but we have to update the max_to_the_right at each node
-        if left < node.interval.left {
-          node.max_to_the_right = node.max_to_the_right.max(right);
-          node.left.insert(left, right, data);
-        } else {
-          node.max_to_the_right = node.max_to_the_right.max(right);
-          node.right.insert(left, right, data);
-        }
-      }
-    }
+  pub fn iter(&self) -> impl Iterator<Item = &Mapping> {
+    self.mappings.values()
   }
 
-  pub fn search(&self, point: u64) -> Option<&IntervalSearchTreeNode> {
-    match self.root {
-      None => None,
-      Some(ref node) => {
-        if point < node.interval.left {
-          // Would be on the left side of the tree
-          node.left.search(point)
-        } else {
-          // On the root or the right side of the tree
-          if point <= node.interval.right {
-            // On the root
-            Some(node)
-          } else {
-            // On the right side of the tree but we can check if it exceeds the max_to_the_right to immediately return None
-            if point <= node.max_to_the_right {
-              node.right.search(point)
-            } else {
-              None
-            }
-          }
-        }
-      }
-    }
+  // Mappings that share at least one number with the range, in order
+  pub fn overlapping(&self, range: &Interval) -> impl Iterator<Item = &Mapping> {
+    // Only the last mapping starting before the range can reach into it
+    let before = self
+      .mappings
+      .range(..range.left)
+      .next_back()
+      .map(|(_, mapping)| mapping)
+      .filter(|mapping| mapping.interval.right >= range.left);
+
+    before.into_iter().chain(
+      self
+        .mappings
+        .range(range.left..=range.right)
+        .map(|(_, mapping)| mapping),
+    )
   }
 
-  fn insert_from_triplet(&mut self, out_start: u64, in_start: u64, range_length: u64) {
+  fn insert_from_triplet(
+    &mut self,
+    out_start: u64,
+    in_start: u64,
+    range_length: u64,
+  ) -> io::Result<()> {
+    if range_length == 0 {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Empty range starting at {}", in_start),
+      ));
+    }
+
     let left = in_start;
-    let right = in_start + range_length - 1;
+    let right = checked(in_start.checked_add(range_length - 1), "Range end")?;
     let data = Interval {
       left: out_start,
-      right: out_start + range_length - 1,
+      right: checked(out_start.checked_add(range_length - 1), "Range end")?,
     };
-    self.insert(left, right, data);
+    self.insert(left, right, data)
   }
 
   pub fn find_output(&self, point: u64) -> u64 {
     self.search(point).map_or_else(
       || point,
-      |node| {
-        let offset = point - node.interval.left;
-        node.data.left + offset
+      |mapping| {
+        let offset = point - mapping.interval.left;
+        mapping.data.left + offset
       },
     )
   }
 
-  pub fn nodes(&self) -> Vec<&IntervalSearchTreeNode> {
-    match self.root {
-      None => vec![],
-      Some(ref node) => {
-        let mut result = vec![node];
-        result.extend(node.left.nodes());
-        result.extend(node.right.nodes());
-        result
-      }
-    }
-  }
-
-  // This absolutely sucks balls but its fast enough for the input
   pub fn remap(&self, input: &Interval) -> Vec<Interval> {
-    let mut nodes = self.nodes();
-    nodes.sort_by(|a, b| a.interval.left.cmp(&b.interval.left));
-
     let mut result = vec![];
 
     let mut previous_end = input.left;
 
-    for node in nodes {
-      // If we have no intersection at all we can skip this node. Otherwise:
-      if let Some(intersection) = node.interval.intersection(input) {
-        // The space between this interval and the last interval needs to be filled by
-        // creating an interval that maps the same space in the output
-
-        let map_direct_left = previous_end;
-        let map_direct_right = intersection.left;
-
-        if map_direct_right > map_direct_left {
-          result.push(Interval {
-            left: map_direct_left,
-            right: map_direct_right - 1,
-          });
-        }
-
-        previous_end = intersection.right + 1;
+    for mapping in self.overlapping(input) {
+      let intersection = mapping
+        .interval
+        .intersection(input)
+        .expect("Overlapping mappings intersect the input");
 
-        // next add the projection of the interval onto the output
+      // The space between this interval and the last interval needs to be filled by
+      // creating an interval that maps the same space in the output
+      if intersection.left > previous_end {
         result.push(Interval {
-          left: intersection.left + node.data.left - node.interval.left,
-          right: intersection.right + node.data.left - node.interval.left,
+          left: previous_end,
+          right: intersection.left - 1,
         });
       }
+
+      previous_end = intersection.right + 1;
+
+      // next add the projection of the interval onto the output
+      result.push(Interval {
+        left: intersection.left + mapping.data.left - mapping.interval.left,
+        right: intersection.right + mapping.data.left - mapping.interval.left,
+      });
     }
 
     // If there is space left at the end we need to fill it
@@ -198,151 +177,300 @@ impl IntervalSearchTree {
   }
 }
 
-#[derive(Debug)]
-struct Input {
-  seeds: Vec<u64>,
-  seed_to_soil: IntervalSearchTree,
-  soil_to_fertilizer: IntervalSearchTree,
-  fertilizer_to_water: IntervalSearchTree,
-  water_to_light: IntervalSearchTree,
-  light_to_temperature: IntervalSearchTree,
-  temperature_to_humidity: IntervalSearchTree,
-  humidity_to_location: IntervalSearchTree,
+// Adds a constant to every number in each piece. The pieces are in order and
+// cover every u64 between them, so any map can be applied after any other.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PiecewiseShift {
+  pieces: Vec<(Interval, i128)>,
 }
 
-impl Input {
-  fn submit(&mut self, state: &ParseState, triplet: (u64, u64, u64)) {
-    let tree = match state {
-      ParseState::Seeds => panic!("Cannot submit triplets to seeds!"),
-      ParseState::SeedToSoil => &mut self.seed_to_soil,
-      ParseState::SoilToFertilizer => &mut self.soil_to_fertilizer,
-      ParseState::FertilizerToWater => &mut self.fertilizer_to_water,
-      ParseState::WaterToLight => &mut self.water_to_light,
-      ParseState::LightToTemperature => &mut self.light_to_temperature,
-      ParseState::TemperatureToHumidity => &mut self.temperature_to_humidity,
-      ParseState::HumidityToLocation => &mut self.humidity_to_location,
-    };
+impl From<&IntervalMap> for PiecewiseShift {
+  fn from(map: &IntervalMap) -> Self {
+    let mut pieces = vec![];
+    let mut next = Some(0);
+
+    for mapping in map.iter() {
+      let left = mapping.interval.left;
+      if let Some(start) = next.filter(|&start| start < left) {
+        pieces.push((
+          Interval {
+            left: start,
+            right: left - 1,
+          },
+          0,
+        ));
+      }
+      let shift = mapping.data.left as i128 - left as i128;
+      pieces.push((mapping.interval, shift));
+      next = mapping.interval.right.checked_add(1);
+    }
 
-    tree.insert_from_triplet(triplet.0, triplet.1, triplet.2)
+    if let Some(start) = next {
+      pieces.push((
+        Interval {
+          left: start,
+          right: u64::MAX,
+        },
+        0,
+      ));
+    }
+
+    PiecewiseShift::merged(pieces)
   }
 }
 
-impl Default for Input {
-  fn default() -> Self {
+impl PiecewiseShift {
+  pub fn identity() -> Self {
     Self {
-      seeds: vec![],
-      seed_to_soil: IntervalSearchTree::new(),
-      soil_to_fertilizer: IntervalSearchTree::new(),
-      fertilizer_to_water: IntervalSearchTree::new(),
-      water_to_light: IntervalSearchTree::new(),
-      light_to_temperature: IntervalSearchTree::new(),
-      temperature_to_humidity: IntervalSearchTree::new(),
-      humidity_to_location: IntervalSearchTree::new(),
+      pieces: vec![(
+        Interval {
+          left: 0,
+          right: u64::MAX,
+        },
+        0,
+      )],
     }
   }
-}
 
-#[derive(Debug, Clone, Copy)]
-enum ParseState {
-  Seeds,
-  SeedToSoil,
-  SoilToFertilizer,
-  FertilizerToWater,
-  WaterToLight,
-  LightToTemperature,
-  TemperatureToHumidity,
-  HumidityToLocation,
-}
+  // Joins neighbouring pieces that shift by the same amount
+  fn merged(pieces: Vec<(Interval, i128)>) -> Self {
+    let mut result: Vec<(Interval, i128)> = vec![];
+    for (interval, shift) in pieces {
+      match result.last_mut() {
+        Some((last, last_shift)) if *last_shift == shift => last.right = interval.right,
+        _ => result.push((interval, shift)),
+      }
+    }
+    Self { pieces: result }
+  }
+
+  // The pieces cut down to the range, in order
+  fn restrict(&self, range: &Interval) -> impl Iterator<Item = (Interval, i128)> + '_ {
+    let first = self
+      .pieces
+      .partition_point(|(interval, _)| interval.right < range.left);
+    let range = *range;
+
+    self.pieces[first..]
+      .iter()
+      .map_while(move |(interval, shift)| Some((interval.intersection(&range)?, *shift)))
+  }
+
+  pub fn get(&self, point: u64) -> u64 {
+    let (interval, shift) = self
+      .restrict(&Interval {
+        left: point,
+        right: point,
+      })
+      .next()
+      .expect("Pieces cover every number");
+    interval.shifted(shift).left
+  }
+
+  pub fn apply(&self, input: &Interval) -> Vec<Interval> {
+    self
+      .restrict(input)
+      .map(|(interval, shift)| interval.shifted(shift))
+      .collect()
+  }
+
+  pub fn apply_set(&self, input: &IntervalSet<u64>) -> IntervalSet<u64> {
+    input
+      .iter()
+      .flat_map(|(left, right)| self.apply(&Interval { left, right }))
+      .map(|interval| (interval.left, interval.right))
+      .collect()
+  }
+
+  // This map followed by the next one
+  pub fn compose(&self, next: &PiecewiseShift) -> PiecewiseShift {
+    let mut pieces = vec![];
+
+    for &(interval, shift) in &self.pieces {
+      for (image, next_shift) in next.restrict(&interval.shifted(shift)) {
+        pieces.push((image.shifted(-shift), shift + next_shift));
+      }
+    }
+
+    PiecewiseShift::merged(pieces)
+  }
 
-impl ParseState {
-  fn next(&self) -> Option<Self> {
-    match self {
-      ParseState::Seeds => Some(ParseState::SeedToSoil),
-      ParseState::SeedToSoil => Some(ParseState::SoilToFertilizer),
-      ParseState::SoilToFertilizer => Some(ParseState::FertilizerToWater),
-      ParseState::FertilizerToWater => Some(ParseState::WaterToLight),
-      ParseState::WaterToLight => Some(ParseState::LightToTemperature),
-      ParseState::LightToTemperature => Some(ParseState::TemperatureToHumidity),
-      ParseState::TemperatureToHumidity => Some(ParseState::HumidityToLocation),
-      ParseState::HumidityToLocation => None,
+  // None unless every number is the image of exactly one other
+  pub fn inverse(&self) -> Option<PiecewiseShift> {
+    let mut pieces = self
+      .pieces
+      .iter()
+      .map(|&(interval, shift)| (interval.shifted(shift), -shift))
+      .collect::<Vec<_>>();
+    pieces.sort_by_key(|(interval, _)| interval.left);
+
+    let mut next = Some(0);
+    for (interval, _) in &pieces {
+      if next != Some(interval.left) {
+        return None;
+      }
+      next = interval.right.checked_add(1);
     }
+
+    next.is_none().then(|| PiecewiseShift::merged(pieces))
   }
 }
 
-fn parse_input<F>(seed_parser: F) -> io::Result<Input>
-where
-  F: Fn(String) -> Vec<u64>,
-{
-  let mut input = Input::default();
-  let mut state = Some(ParseState::Seeds);
+// One "x-to-y map:" section of the almanac
+#[derive(Debug)]
+struct Conversion {
+  source: String,
+  destination: String,
+  map: IntervalMap,
+}
 
-  let mut result = read_day(5)?.filter_map(|s| {
-    let trimmed = s.trim();
-    if trimmed.is_empty() {
-      None
-    } else {
-      Some(trimmed.to_string())
-    }
-  });
+#[derive(Debug)]
+struct Input {
+  seeds: Vec<u64>,
+  conversions: Vec<Conversion>,
+}
+
+impl Input {
+  fn parse<F>(lines: impl Iterator<Item = String>, seed_parser: F) -> io::Result<Input>
+  where
+    F: Fn(String) -> Vec<u64>,
+  {
+    let mut lines = lines.filter_map(|s| {
+      let trimmed = s.trim();
+      if trimmed.is_empty() {
+        None
+      } else {
+        Some(trimmed.to_string())
+      }
+    });
 
-  let seeds_raw = result.next().expect("Expected seeds");
+    let seeds_raw = lines
+      .next()
+      .ok_or(io::Error::new(io::ErrorKind::InvalidData, "Expected seeds"))?;
 
-  let seeds = seed_parser(seeds_raw);
+    let mut input = Input {
+      seeds: seed_parser(seeds_raw),
+      conversions: vec![],
+    };
 
-  input.seeds = seeds;
+    for line in lines {
+      // Sections start with a header like "seed-to-soil map:"
+      if let Some(name) = line.strip_suffix(" map:") {
+        let (source, destination) = name.split_once("-to-").ok_or(io::Error::new(
+          io::ErrorKind::InvalidData,
+          format!("Invalid map header {}", line),
+        ))?;
+
+        if input
+          .conversions
+          .iter()
+          .any(|c| c.source == source && c.destination == destination)
+        {
+          return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Duplicate map {}", name),
+          ));
+        }
 
-  for line in result {
-    if !line.is_empty() {
-      // Check if we are at a boundary. That is when the first charachter is a letter
-      let first_char = line.chars().next().expect("Expected a first char");
-      if first_char.is_alphabetic() {
-        state = state.and_then(|s| s.next());
+        input.conversions.push(Conversion {
+          source: source.to_string(),
+          destination: destination.to_string(),
+          map: IntervalMap::new(),
+        });
       } else {
         let triplet = line
-          .split(" ")
-          .filter_map(|s| {
-            let trimmed = s.trim();
-            if trimmed.is_empty() {
-              None
-            } else {
-              trimmed.parse().ok()
-            }
-          })
-          .collect::<Vec<u64>>();
+          .split_whitespace()
+          .map(|s| s.parse::<u64>())
+          .collect::<Result<Vec<u64>, _>>()
+          .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let [out_start, in_start, range_length] = triplet[..] else {
+          return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Expected a triplet but got {:?}", triplet),
+          ));
+        };
+
+        let conversion = input.conversions.last_mut().ok_or(io::Error::new(
+          io::ErrorKind::InvalidData,
+          "Expected a map header",
+        ))?;
+
+        conversion
+          .map
+          .insert_from_triplet(out_start, in_start, range_length)?;
+      }
+    }
 
-        if triplet.len() != 3 {
-          panic!("Expected a triplet but got {:?}", triplet);
-        }
+    Ok(input)
+  }
 
-        let s = &state.expect("Expected a state");
+  // Chains maps together to get from one category to another, through as few
+  // maps as possible. Longer chains are never looked at, but equally short ones
+  // have to agree, since there is no telling which one was meant.
+  fn conversion(&self, from: &str, to: &str) -> io::Result<PiecewiseShift> {
+    // Distance from `from`, with every different shift the shortest chains give
+    let mut reached: HashMap<&str, (usize, Vec<PiecewiseShift>)> =
+      HashMap::from([(from, (0, vec![PiecewiseShift::identity()]))]);
+    let mut queue = VecDeque::from([from]);
+
+    while let Some(category) = queue.pop_front() {
+      let (depth, shifts) = reached[category].clone();
+      if reached.get(to).is_some_and(|&(found, _)| depth >= found) {
+        break;
+      }
 
-        input.submit(s, (triplet[0], triplet[1], triplet[2]));
+      for conversion in self.conversions.iter().filter(|c| c.source == category) {
+        let map = PiecewiseShift::from(&conversion.map);
+        let destination = conversion.destination.as_str();
+        let (found, chains) = reached.entry(destination).or_insert_with(|| {
+          queue.push_back(destination);
+          (depth + 1, vec![])
+        });
+        if *found != depth + 1 {
+          continue;
+        }
+        for shift in &shifts {
+          let chain = shift.compose(&map);
+          if !chains.contains(&chain) {
+            chains.push(chain);
+          }
+        }
       }
     }
+
+    let (_, mut chains) = reached.remove(to).ok_or(io::Error::new(
+      io::ErrorKind::NotFound,
+      format!("No way to convert {} to {}", from, to),
+    ))?;
+    if chains.len() > 1 {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Maps from {} to {} disagree", from, to),
+      ));
+    }
+    Ok(chains.pop().unwrap())
   }
+}
 
-  Ok(input)
+fn parse_input<F>(seed_parser: F) -> io::Result<Input>
+where
+  F: Fn(String) -> Vec<u64>,
+{
+  Input::parse(read_day(5)?, seed_parser)
 }
 
 fn find_min_location<F>(seed_parser: F) -> io::Result<u64>
 where
   F: Fn(String) -> Vec<u64>,
 {
-  let input = parse_input(seed_parser).unwrap();
+  let input = parse_input(seed_parser)?;
+  let seed_to_location = input.conversion("seed", "location")?;
 
   let result = input
     .seeds
     .iter()
-    .map(|seed| {
-      let soil = input.seed_to_soil.find_output(*seed);
-      let fertilizer = input.soil_to_fertilizer.find_output(soil);
-      let water = input.fertilizer_to_water.find_output(fertilizer);
-      let light = input.water_to_light.find_output(water);
-      let temperature = input.light_to_temperature.find_output(light);
-      let humidity = input.temperature_to_humidity.find_output(temperature);
-      let location = input.humidity_to_location.find_output(humidity);
-      location
-    })
+    .map(|seed| seed_to_location.get(*seed))
     .min();
 
   result.ok_or(io::Error::new(io::ErrorKind::Other, "No result"))
@@ -414,36 +542,17 @@ pub fn part_2() -> io::Result<u64> {
       .collect::<Vec<u64>>()
   };
 
-  let input = parse_input(seed_pairs).unwrap();
-
-  let mut seed_intervals = vec![];
-
-  let mut index = 0;
-  while index + 1 < input.seeds.len() {
-    let first = input.seeds[index];
-    let second = input.seeds[index + 1];
-
-    let interval = Interval {
-      left: first,
-      right: first + second - 1,
-    };
-
-    seed_intervals.push(interval);
+  let input = parse_input(seed_pairs)?;
 
-    index += 2;
-  }
+  // Overlapping seed ranges only need converting once
+  let seeds = input
+    .seeds
+    .chunks_exact(2)
+    .map(|pair| (pair[0], pair[0] + pair[1] - 1))
+    .collect::<IntervalSet<u64>>();
 
-  let result = seed_intervals
-    .iter()
-    .flat_map(|interval| input.seed_to_soil.remap(interval))
-    .flat_map(|interval| input.soil_to_fertilizer.remap(&interval))
-    .flat_map(|interval| input.fertilizer_to_water.remap(&interval))
-    .flat_map(|interval| input.water_to_light.remap(&interval))
-    .flat_map(|interval| input.light_to_temperature.remap(&interval))
-    .flat_map(|interval| input.temperature_to_humidity.remap(&interval))
-    .flat_map(|interval| input.humidity_to_location.remap(&interval))
-    .map(|interval| interval.left)
-    .min();
+  let seed_to_location = input.conversion("seed", "location")?;
+  let result = seed_to_location.apply_set(&seeds).min();
 
   result.ok_or(io::Error::new(io::ErrorKind::Other, "No result"))
 }
@@ -452,61 +561,72 @@ pub fn part_2() -> io::Result<u64> {
 mod test {
   use super::*;
 
-  #[test]
-  fn insert_works() {
-    let mut ist = IntervalSearchTree::new();
-
-    let i = Interval { left: 1, right: 2 };
-
-    ist.insert(17, 19, i);
-    ist.insert(5, 8, i);
-    ist.insert(21, 24, i);
-    ist.insert(4, 8, i);
-    ist.insert(15, 18, i);
-    ist.insert(7, 10, i);
-    ist.insert(16, 22, i);
-
-    assert_eq!(
-      ist.string_repr(),
-      "Node(L:17, R:19, M:24, Left:Node(L:5, R:8, M:22, Left:Node(L:4, R:8, M:8, Left:Leaf, Right:Leaf), Right:Node(L:15, R:18, M:22, Left:Node(L:7, R:10, M:10, Left:Leaf, Right:Leaf), Right:Node(L:16, R:22, M:22, Left:Leaf, Right:Leaf))), Right:Node(L:21, R:24, M:24, Left:Leaf, Right:Leaf))"
-    );
-  }
-
   #[test]
   fn search() {
-    let mut ist = IntervalSearchTree::new();
+    let mut map = IntervalMap::new();
 
-    ist.insert(17, 19, Interval { left: 1, right: 1 });
-    ist.insert(5, 8, Interval { left: 2, right: 2 });
-    ist.insert(21, 24, Interval { left: 3, right: 3 });
-    ist.insert(4, 8, Interval { left: 4, right: 4 });
-    ist.insert(15, 18, Interval { left: 5, right: 5 });
-    ist.insert(7, 10, Interval { left: 6, right: 6 });
-    ist.insert(16, 22, Interval { left: 7, right: 7 });
+    map.insert(17, 19, Interval { left: 1, right: 1 }).unwrap();
+    map.insert(5, 8, Interval { left: 2, right: 2 }).unwrap();
+    map.insert(21, 24, Interval { left: 3, right: 3 }).unwrap();
+    map.insert(9, 10, Interval { left: 6, right: 6 }).unwrap();
+    map.insert(15, 16, Interval { left: 5, right: 5 }).unwrap();
 
     assert_eq!(
-      ist.search(22).map(|node| node.data),
+      map.search(22).map(|mapping| mapping.data),
       Some(Interval { left: 3, right: 3 })
     );
     assert_eq!(
-      ist.search(9).map(|node| node.data),
+      map.search(9).map(|mapping| mapping.data),
       Some(Interval { left: 6, right: 6 })
     );
     assert_eq!(
-      ist.search(4).map(|node| node.data),
-      Some(Interval { left: 4, right: 4 })
+      map.search(5).map(|mapping| mapping.data),
+      Some(Interval { left: 2, right: 2 })
+    );
+    assert_eq!(map.search(20).map(|mapping| mapping.data), None);
+    assert_eq!(map.search(1).map(|mapping| mapping.data), None);
+    assert_eq!(map.search(25).map(|mapping| mapping.data), None);
+
+    assert!(map.insert(4, 5, Interval { left: 0, right: 1 }).is_err());
+    assert!(map.insert(18, 20, Interval { left: 0, right: 2 }).is_err());
+
+    let lefts = |mappings: Vec<&Mapping>| {
+      mappings
+        .iter()
+        .map(|mapping| mapping.interval.left)
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(lefts(map.iter().collect()), vec![5, 9, 15, 17, 21]);
+    assert_eq!(
+      lefts(
+        map
+          .overlapping(&Interval {
+            left: 10,
+            right: 17
+          })
+          .collect()
+      ),
+      vec![9, 15, 17]
+    );
+    assert_eq!(
+      lefts(
+        map
+          .overlapping(&Interval {
+            left: 11,
+            right: 14
+          })
+          .collect()
+      ),
+      vec![]
     );
-    assert_eq!(ist.search(20).map(|node| node.data), None);
-    assert_eq!(ist.search(1).map(|node| node.data), None);
-    assert_eq!(ist.search(25).map(|node| node.data), None);
   }
 
   #[test]
   fn test_remap() {
-    let mut ist = IntervalSearchTree::new();
-    ist.insert(0, 1, Interval { left: 1, right: 2 });
+    let mut map = IntervalMap::new();
+    map.insert(0, 1, Interval { left: 1, right: 2 }).unwrap();
 
-    let result = ist.remap(&Interval { left: 0, right: 3 });
+    let result = map.remap(&Interval { left: 0, right: 3 });
 
     assert_eq!(
       result,
@@ -516,34 +636,40 @@ mod test {
       ]
     );
 
-    let mut ist = IntervalSearchTree::new();
+    let mut map = IntervalMap::new();
 
-    ist.insert(
-      0,
-      2,
-      Interval {
-        left: 10,
-        right: 12,
-      },
-    );
-    ist.insert(
-      4,
-      6,
-      Interval {
-        left: 14,
-        right: 16,
-      },
-    );
-    ist.insert(
-      8,
-      10,
-      Interval {
-        left: 18,
-        right: 20,
-      },
-    );
+    map
+      .insert(
+        0,
+        2,
+        Interval {
+          left: 10,
+          right: 12,
+        },
+      )
+      .unwrap();
+    map
+      .insert(
+        4,
+        6,
+        Interval {
+          left: 14,
+          right: 16,
+        },
+      )
+      .unwrap();
+    map
+      .insert(
+        8,
+        10,
+        Interval {
+          left: 18,
+          right: 20,
+        },
+      )
+      .unwrap();
 
-    let result = ist.remap(&Interval { left: 1, right: 9 });
+    let result = map.remap(&Interval { left: 1, right: 9 });
 
     assert_eq!(
       result,
@@ -565,4 +691,180 @@ mod test {
       ]
     );
   }
+
+  #[test]
+  fn composes_and_inverts() {
+    let map = |triplets: &[(u64, u64, u64)]| {
+      let mut map = IntervalMap::new();
+      for &(out_start, in_start, length) in triplets {
+        map
+          .insert_from_triplet(out_start, in_start, length)
+          .unwrap();
+      }
+      map
+    };
+    let seed_to_soil = map(&[(50, 98, 2), (52, 50, 48)]);
+    let soil_to_fertilizer = map(&[(0, 15, 37), (37, 52, 2), (39, 0, 15)]);
+
+    let chain =
+      PiecewiseShift::from(&seed_to_soil).compose(&PiecewiseShift::from(&soil_to_fertilizer));
+    for seed in 0..200 {
+      let fertilizer = soil_to_fertilizer.find_output(seed_to_soil.find_output(seed));
+      assert_eq!(chain.get(seed), fertilizer);
+    }
+
+    let mut locations = chain.apply(&Interval {
+      left: 79,
+      right: 92,
+    });
+    locations.sort_by_key(|interval| interval.left);
+    assert_eq!(
+      locations,
+      vec![Interval {
+        left: 81,
+        right: 94
+      }]
+    );
+
+    let inverse = chain.inverse().unwrap();
+    for seed in 0..200 {
+      assert_eq!(inverse.get(chain.get(seed)), seed);
+    }
+    assert_eq!(chain.compose(&inverse), PiecewiseShift::identity());
+
+    // Two ranges landing on the same numbers can't be undone
+    let squashed = map(&[(0, 10, 5)]);
+    assert_eq!(PiecewiseShift::from(&squashed).inverse(), None);
+  }
+
+  #[test]
+  fn rejects_bad_triplets() {
+    let mut map = IntervalMap::new();
+    assert!(map.insert_from_triplet(0, 10, 0).is_err());
+    assert!(map.insert_from_triplet(0, u64::MAX, 2).is_err());
+    assert!(map.insert_from_triplet(u64::MAX, 0, 2).is_err());
+
+    // A range can still reach the very last number
+    assert!(map.insert_from_triplet(0, u64::MAX - 1, 2).is_ok());
+  }
+
+  const EXAMPLE: &str = "seeds: 79 14 55 13
+
+seed-to-soil map:
+50 98 2
+52 50 48
+
+soil-to-fertilizer map:
+0 15 37
+37 52 2
+39 0 15
+
+fertilizer-to-water map:
+49 53 8
+0 11 42
+42 0 7
+57 7 4
+
+water-to-light map:
+88 18 7
+18 25 70
+
+light-to-temperature map:
+45 77 23
+81 45 19
+68 64 13
+
+temperature-to-humidity map:
+0 69 1
+1 0 69
+
+humidity-to-location map:
+60 56 37
+56 93 4";
+
+  fn parse(input: &str) -> Input {
+    Input::parse(input.lines().map(String::from), |line| {
+      line.split(' ').filter_map(|s| s.parse().ok()).collect()
+    })
+    .unwrap()
+  }
+
+  #[test]
+  fn converts_between_any_categories() {
+    let input = parse(EXAMPLE);
+    let seed_to_location = input.conversion("seed", "location").unwrap();
+    assert_eq!(
+      input
+        .seeds
+        .iter()
+        .map(|&seed| seed_to_location.get(seed))
+        .collect::<Vec<_>>(),
+      vec![82, 43, 86, 35]
+    );
+    let seeds = [(79, 92), (55, 67)]
+      .into_iter()
+      .collect::<IntervalSet<u64>>();
+    assert_eq!(seed_to_location.apply_set(&seeds).min(), Some(46));
+
+    // Seed 79 has soil 81, fertilizer 81, water 81 and light 74
+    assert_eq!(input.conversion("soil", "light").unwrap().get(81), 74);
+    assert_eq!(
+      input.conversion("water", "water").unwrap(),
+      PiecewiseShift::identity()
+    );
+    assert!(input.conversion("location", "seed").is_err());
+    assert!(input.conversion("seed", "colour").is_err());
+
+    // Maps can branch and come back together, in any order in the file
+    let input = parse(
+      "seeds: 1
+
+soil-to-location map:
+100 0 10
+
+seed-to-soil map:
+5 0 5
+
+seed-to-water map:
+0 0 1
+
+water-to-location map:
+7 0 1",
+    );
+    assert_eq!(input.conversion("soil", "location").unwrap().get(1), 101);
+    assert_eq!(input.conversion("water", "location").unwrap().get(0), 7);
+    // Through soil seed 1 ends up at 106, through water at 1
+    assert!(input.conversion("seed", "location").is_err());
+
+    // Equally short chains that agree are fine, and shorter ones win outright.
+    // Both chains swap two blocks and swap them back.
+    let input = parse(
+      "seeds: 1
+
+seed-to-soil map:
+10 0 5
+0  10   5
+
+soil-to-location map:
+0 10 5
+10 0 5
+
+seed-to-water map:
+100 0 5
+0 100 5
+
+water-to-location map:
+0 100 5
+100 0 5
+
+location-to-humidity map:
+0 0 100
+
+seed-to-humidity map:
+50 0 5",
+    );
+    let seed_to_location = input.conversion("seed", "location").unwrap();
+    assert_eq!(seed_to_location, PiecewiseShift::identity());
+    assert_eq!(input.conversion("seed", "humidity").unwrap().get(1), 51);
+  }
 }
@@ -1,11 +1,15 @@
 use std::io;
 
-use crate::fs::read_day;
+use crate::{
+  checked::{narrow, product},
+  fs::read_day,
+};
 
 #[derive(Debug)]
 struct Race {
-  time: u64,
-  distance: u64,
+  time: u128,
+  distance: u128,
+  acceleration: u128,
 }
 
 fn parse_input_1() -> io::Result<Vec<Race>> {
@@ -22,9 +26,10 @@ fn parse_input_1() -> io::Result<Vec<Race>> {
   let races = lines[0]
     .iter()
     .zip(lines[1].iter())
-    .map(|(time, distance)| Race {
-      time: *time,
-      distance: *distance,
+    .map(|(&time, &distance)| Race {
+      time: time as u128,
+      distance: distance as u128,
+      acceleration: 1,
     })
     .collect::<Vec<_>>();
 
@@ -49,67 +54,161 @@ fn parse_input_2() -> io::Result<Race> {
   lines
     .get(0)
     .zip(lines.get(1))
-    .map(|(&time, &distance)| Race { time, distance })
+    .map(|(&time, &distance)| Race {
+      time: time as u128,
+      distance: distance as u128,
+      acceleration: 1,
+    })
     .ok_or(io::Error::new(io::ErrorKind::Other, "Input borken"))
 }
 
-// distance travelled for max time t when holding the button for v seconds:
-// x(t, v) = -v² + tv
+// distance travelled for max time t when holding the button for v seconds, with
+// the boat gaining a speed of a per second held:
+// x(t, v) = a(tv - v²)
 // Ideal time to hold is then t/2
-// If the current record travelled distance d then it held the button for:
-// v² - tv + d = 0 => (t ± √(t² - 4d))/2
-// We can find all the integer numbers between the ideal time and the current record.
+// a·y > d is the same as y > d / a (rounded down) for whole y, so the record can
+// be scaled down to r = d / a. Holding for v beats it when v² - tv + r < 0, i.e.
+// strictly between (t ± √(t² - 4r))/2.
 // Since this forms a parabola its symmetric around the ideal time
-// so we can reflect half the solution to get the full one.
-
-// Count integes between x and y where smaller side is exclusive and bigger side is inclusives
-fn ints_between(x: f64, y: f64) -> u64 {
-  match f64::total_cmp(&x, &y) {
-    std::cmp::Ordering::Less => {
-      let lower: u64 = f64::floor(x + 1f64) as u64;
-      let higher: u64 = y as u64;
-      higher - lower + 1
+// so the winning holds are [v, t - v] for the smallest winning v.
+
+impl Race {
+  fn record(&self) -> u128 {
+    self.distance / self.acceleration
+  }
+
+  fn beats_record(&self, hold: u128) -> bool {
+    // Too big to multiply is certainly bigger than the record
+    hold
+      .checked_mul(self.time - hold)
+      .is_none_or(|reached| reached > self.record())
+  }
+
+  // Smallest winning hold. The quadratic formula gets within one of it, which is
+  // then corrected by checking either side. t² only fits when t does in a u64,
+  // beyond that it falls back to a binary search.
+  fn first_winning_hold(&self) -> Option<u128> {
+    let half = self.time / 2;
+    if self.acceleration == 0 || !self.beats_record(half) {
+      return None;
+    }
+
+    let discriminant = self
+      .time
+      .checked_mul(self.time)
+      .zip(self.record().checked_mul(4))
+      .map(|(square, record)| square - record);
+
+    let Some(discriminant) = discriminant else {
+      return Some(first_winning_hold_by_search(self, 0, half));
+    };
+
+    let mut hold = ((self.time - discriminant.isqrt()) / 2).min(half);
+    while hold > 0 && self.beats_record(hold - 1) {
+      hold -= 1;
     }
-    std::cmp::Ordering::Equal => 0u64,
-    std::cmp::Ordering::Greater => ints_between(y, x),
+    while !self.beats_record(hold) {
+      hold += 1;
+    }
+    Some(hold)
+  }
+
+  // Every hold time that wins, inclusive
+  fn winning_holds(&self) -> Option<(u128, u128)> {
+    self
+      .first_winning_hold()
+      .map(|hold| (hold, self.time - hold))
+  }
+
+  fn ways_to_win(&self) -> u128 {
+    self
+      .winning_holds()
+      .map_or(0, |(first, last)| last - first + 1)
   }
 }
 
-pub fn part_1() -> io::Result<u64> {
-  let result: u64 = parse_input_1()?
-    .iter()
-    .map(|&Race { time, distance }| {
-      let t: f64 = time as f64;
-      let d: f64 = distance as f64;
-      let ideal_time = t / 2f64;
-      let opponent_time: f64 = (t - f64::sqrt(t * t - 4f64 * d)) / 2f64;
-      let between = ints_between(opponent_time, ideal_time);
-      let mut result = 2 * between;
-      if time % 2 == 0 {
-        result = result.saturating_sub(1);
-      }
-      result
-    })
-    .product();
+// The lowest winning hold in [lo, hi], given that hi wins
+fn first_winning_hold_by_search(race: &Race, mut lo: u128, mut hi: u128) -> u128 {
+  while lo < hi {
+    let mid = lo + (hi - lo) / 2;
+    if race.beats_record(mid) {
+      hi = mid;
+    } else {
+      lo = mid + 1;
+    }
+  }
+  lo
+}
 
-  Ok(result)
+pub fn part_1() -> io::Result<u64> {
+  narrow(product(
+    parse_input_1()?.iter().map(|race| race.ways_to_win()),
+  )?)
 }
 
 pub fn part_2() -> io::Result<u64> {
-  let result = match parse_input_2()? {
-    Race { time, distance } => {
-      let t: f64 = time as f64;
-      let d: f64 = distance as f64;
-      let ideal_time = t / 2f64;
-      let opponent_time: f64 = (t - f64::sqrt(t * t - 4f64 * d)) / 2f64;
-      let between = ints_between(opponent_time, ideal_time);
-      let mut result = 2 * between;
-      if time % 2 == 0 {
-        result = result.saturating_sub(1);
+  narrow(parse_input_2()?.ways_to_win())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn race(time: u128, distance: u128, acceleration: u128) -> Race {
+    Race {
+      time,
+      distance,
+      acceleration,
+    }
+  }
+
+  #[test]
+  fn counts_example_races() {
+    let ways = [(7, 9), (15, 40), (30, 200)]
+      .into_iter()
+      .map(|(time, distance)| race(time, distance, 1).ways_to_win())
+      .collect::<Vec<_>>();
+    assert_eq!(ways, vec![4, 8, 9]);
+    assert_eq!(race(71530, 940200, 1).ways_to_win(), 71503);
+    assert_eq!(race(30, 200, 1).winning_holds(), Some((11, 19)));
+  }
+
+  #[test]
+  fn matches_brute_force() {
+    // Includes perfect square discriminants, e.g. t = 10, d = 16 has roots 2 and 8
+    for acceleration in 0..4 {
+      for time in 0..40 {
+        for distance in 0..300 {
+          let race = race(time, distance, acceleration);
+          let winning = (0..=time)
+            .filter(|hold| acceleration * hold * (time - hold) > distance)
+            .collect::<Vec<_>>();
+          assert_eq!(
+            race.winning_holds(),
+            winning.first().zip(winning.last()).map(|(&a, &b)| (a, b))
+          );
+          assert_eq!(race.ways_to_win(), winning.len() as u128);
+        }
       }
-      result
     }
-  };
+  }
+
+  #[test]
+  fn exact_for_huge_races() {
+    // Past 2^53 an f64 can't tell neighbouring holds apart
+    for (time, distance) in [
+      (u64::MAX as u128, (u64::MAX as u128 / 2).pow(2)),
+      (1 << 60, (1 << 118) - 1),
+      (123_456_789_123, 3_810_394_786_213_210_000),
+      (1 << 100, 1 << 126),
+    ] {
+      let race = race(time, distance, 3);
+      let hold = race.first_winning_hold().unwrap();
+      assert!(race.beats_record(hold));
+      assert!(hold == 0 || !race.beats_record(hold - 1));
+      assert_eq!(hold, first_winning_hold_by_search(&race, 0, time / 2));
+    }
 
-  Ok(result)
+    assert_eq!(race(u128::MAX, u128::MAX, 1).ways_to_win(), u128::MAX - 3);
+  }
 }
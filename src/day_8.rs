@@ -1,6 +1,9 @@
 use std::{collections::HashMap, io};
 
-use crate::fs::read_day;
+use crate::{
+  checked::{lcm_all, narrow},
+  fs::read_day,
+};
 
 #[derive(Debug, Clone, Copy)]
 enum Direction {
@@ -145,30 +148,6 @@ pub fn part_1() -> io::Result<usize> {
   Ok(i + 1)
 }
 
-fn lcm(first: usize, second: usize) -> usize {
-  first * second / gcd(first, second)
-}
-
-fn gcd(first: usize, second: usize) -> usize {
-  let mut max = first;
-  let mut min = second;
-  if min > max {
-    let val = max;
-    max = min;
-    min = val;
-  }
-
-  loop {
-    let res = max % min;
-    if res == 0 {
-      return min;
-    }
-
-    max = min;
-    min = res;
-  }
-}
-
 pub fn part_2() -> io::Result<usize> {
   let input = parse_input()?;
 
@@ -211,10 +190,7 @@ pub fn part_2() -> io::Result<usize> {
     .map(|node| find_steps_to_terminal_node(&input, node))
     .collect::<Vec<_>>();
 
-  let mut x = terminal_steps[0];
-  for i in 1..terminal_steps.len() {
-    x = lcm(x, terminal_steps[i]);
-  }
-
-  Ok(x)
+  narrow(lcm_all(
+    terminal_steps.into_iter().map(|steps| steps as u128),
+  )?)
 }
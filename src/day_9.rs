@@ -1,49 +1,37 @@
 use std::io;
 
-use crate::fs::read_day;
-
-fn pairwise_diff(numbers: Vec<i64>) -> Vec<i64> {
-  numbers
-    .iter()
-    .zip(numbers.iter().skip(1))
-    .map(|(a, b)| b - a)
-    .collect()
-}
+use crate::{checked::checked, fs::read_day, sequence::Polynomial};
 
 fn solve<F>(predict: F) -> io::Result<i64>
 where
-  F: Fn(Vec<i64>) -> i64,
+  F: Fn(&Polynomial) -> io::Result<i64>,
 {
-  let result = read_day(9)?
+  read_day(9)?
     .map(|line| {
-      line
+      let numbers = line
         .split(" ")
         .map(|s| s.trim().parse::<i64>().unwrap())
-        .collect::<Vec<_>>()
+        .collect::<Vec<_>>();
+
+      let polynomial = Polynomial::fit(&numbers)?;
+      if polynomial.is_polynomial() {
+        predict(&polynomial)
+      } else {
+        Err(io::Error::new(
+          io::ErrorKind::InvalidData,
+          format!("Can't tell what comes after {}", line),
+        ))
+      }
+    })
+    .try_fold(0i64, |sum, prediction| {
+      checked(sum.checked_add(prediction?), "Sum")
     })
-    .map(predict)
-    .sum();
-  Ok(result)
 }
 
 pub fn part_1() -> io::Result<i64> {
-  fn predict_next(numbers: Vec<i64>) -> i64 {
-    match numbers.last() {
-      Some(&last) => last + predict_next(pairwise_diff(numbers)),
-      None => 0,
-    }
-  }
-
-  solve(predict_next)
+  solve(|polynomial| polynomial.forward(1))
 }
 
 pub fn part_2() -> io::Result<i64> {
-  fn predict_next(numbers: Vec<i64>) -> i64 {
-    match numbers.first() {
-      Some(&head) => head - predict_next(pairwise_diff(numbers)),
-      None => 0,
-    }
-  }
-
-  solve(predict_next)
+  solve(|polynomial| polynomial.backward(1))
 }
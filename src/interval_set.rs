@@ -0,0 +1,251 @@
+use std::fmt::Debug;
+
+// Integers that can bound an interval. Lengths are counted in u128 so a range
+// covering every u64 still fits.
+pub trait Point: Copy + Ord + Debug {
+  const MIN: Self;
+  const MAX: Self;
+
+  fn succ(self) -> Option<Self>;
+  fn pred(self) -> Option<Self>;
+
+  // Number of points from lo to hi inclusive
+  fn count(lo: Self, hi: Self) -> u128;
+}
+
+macro_rules! impl_point {
+  ($($t:ty),*) => {
+    $(
+      impl Point for $t {
+        const MIN: Self = <$t>::MIN;
+        const MAX: Self = <$t>::MAX;
+
+        fn succ(self) -> Option<Self> {
+          self.checked_add(1)
+        }
+
+        fn pred(self) -> Option<Self> {
+          self.checked_sub(1)
+        }
+
+        fn count(lo: Self, hi: Self) -> u128 {
+          if lo > hi {
+            0
+          } else {
+            (hi as i128 - lo as i128) as u128 + 1
+          }
+        }
+      }
+    )*
+  };
+}
+
+impl_point!(i32, i64, u32, u64, usize);
+
+// Inclusive ranges kept sorted, disjoint and with gaps between them, so two sets
+// with the same points always look the same.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IntervalSet<T: Point> {
+  ranges: Vec<(T, T)>,
+}
+
+impl<T: Point> Default for IntervalSet<T> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<T: Point> FromIterator<(T, T)> for IntervalSet<T> {
+  fn from_iter<I: IntoIterator<Item = (T, T)>>(iter: I) -> Self {
+    let mut ranges = iter
+      .into_iter()
+      .filter(|(lo, hi)| lo <= hi)
+      .collect::<Vec<_>>();
+    ranges.sort();
+
+    let mut result: Vec<(T, T)> = vec![];
+    for (lo, hi) in ranges {
+      match result.last_mut() {
+        // Overlapping or touching, e.g. [1, 3] and [4, 6]
+        Some((_, last)) if last.succ().is_none_or(|next| lo <= next) => {
+          *last = (*last).max(hi);
+        }
+        _ => result.push((lo, hi)),
+      }
+    }
+
+    Self { ranges: result }
+  }
+}
+
+impl<T: Point> IntervalSet<T> {
+  pub fn new() -> Self {
+    Self { ranges: vec![] }
+  }
+
+  pub fn range(lo: T, hi: T) -> Self {
+    [(lo, hi)].into_iter().collect()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.ranges.is_empty()
+  }
+
+  // How many points the set covers
+  pub fn len(&self) -> u128 {
+    self.ranges.iter().map(|&(lo, hi)| T::count(lo, hi)).sum()
+  }
+
+  pub fn min(&self) -> Option<T> {
+    self.ranges.first().map(|&(lo, _)| lo)
+  }
+
+  pub fn max(&self) -> Option<T> {
+    self.ranges.last().map(|&(_, hi)| hi)
+  }
+
+  pub fn contains(&self, point: T) -> bool {
+    let index = self.ranges.partition_point(|&(_, hi)| hi < point);
+    self.ranges.get(index).is_some_and(|&(lo, _)| lo <= point)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (T, T)> + '_ {
+    self.ranges.iter().copied()
+  }
+
+  pub fn insert(&mut self, lo: T, hi: T) {
+    *self = self.iter().chain([(lo, hi)]).collect();
+  }
+
+  pub fn union(&self, other: &IntervalSet<T>) -> IntervalSet<T> {
+    self.iter().chain(other.iter()).collect()
+  }
+
+  pub fn intersection(&self, other: &IntervalSet<T>) -> IntervalSet<T> {
+    let mut result = vec![];
+    let (mut i, mut j) = (0, 0);
+
+    while i < self.ranges.len() && j < other.ranges.len() {
+      let (a_lo, a_hi) = self.ranges[i];
+      let (b_lo, b_hi) = other.ranges[j];
+
+      let lo = a_lo.max(b_lo);
+      let hi = a_hi.min(b_hi);
+      if lo <= hi {
+        result.push((lo, hi));
+      }
+
+      // Whichever ends first can't overlap anything else
+      if a_hi < b_hi {
+        i += 1;
+      } else {
+        j += 1;
+      }
+    }
+
+    Self { ranges: result }
+  }
+
+  // Points between lo and hi that aren't in the set
+  pub fn complement(&self, lo: T, hi: T) -> IntervalSet<T> {
+    let mut result = vec![];
+    let mut next = Some(lo);
+
+    for (range_lo, range_hi) in self.iter() {
+      let Some(start) = next else {
+        break;
+      };
+      if range_lo > start {
+        result.push((start, range_lo.pred().unwrap().min(hi)));
+      }
+      next = next.max(range_hi.succ());
+      if range_hi == T::MAX {
+        next = None;
+      }
+    }
+
+    if let Some(start) = next {
+      result.push((start, hi));
+    }
+
+    result.into_iter().collect()
+  }
+
+  pub fn difference(&self, other: &IntervalSet<T>) -> IntervalSet<T> {
+    self.intersection(&other.complement(T::MIN, T::MAX))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::BTreeSet;
+
+  use super::*;
+  use crate::test_util::Rng;
+
+  fn random_set(rng: &mut Rng) -> IntervalSet<i64> {
+    (0..rng.next(5))
+      .map(|_| {
+        let lo = rng.next(40) as i64 - 20;
+        (lo, lo + rng.next(8) as i64 - 1)
+      })
+      .collect()
+  }
+
+  fn points(set: &IntervalSet<i64>) -> BTreeSet<i64> {
+    set.iter().flat_map(|(lo, hi)| lo..=hi).collect()
+  }
+
+  #[test]
+  fn normalises() {
+    let set = [(5, 7), (1, 3), (4, 4), (10, 9), (12, 20), (13, 14)]
+      .into_iter()
+      .collect::<IntervalSet<u64>>();
+    assert_eq!(set.iter().collect::<Vec<_>>(), vec![(1, 7), (12, 20)]);
+    assert_eq!(set.len(), 16);
+    assert_eq!((set.min(), set.max()), (Some(1), Some(20)));
+    assert!(set.contains(7) && !set.contains(8) && set.contains(12));
+
+    let everything = IntervalSet::range(0, u64::MAX);
+    assert_eq!(everything.len(), 1 << 64);
+    assert_eq!(everything.complement(0, u64::MAX), IntervalSet::new());
+    assert_eq!(
+      IntervalSet::range(0, 5).complement(0, u64::MAX),
+      IntervalSet::range(6, u64::MAX)
+    );
+  }
+
+  #[test]
+  fn agrees_with_point_sets() {
+    let mut rng = Rng::new();
+
+    for _ in 0..1000 {
+      let a = random_set(&mut rng);
+      let b = random_set(&mut rng);
+      let (pa, pb) = (points(&a), points(&b));
+
+      assert_eq!(points(&a.union(&b)), &pa | &pb);
+      assert_eq!(points(&a.intersection(&b)), &pa & &pb);
+      assert_eq!(points(&a.difference(&b)), &pa - &pb);
+
+      let (lo, hi) = (-10, 10);
+      let within = (lo..=hi).collect::<BTreeSet<_>>();
+      assert_eq!(points(&a.complement(lo, hi)), &within - &pa);
+
+      assert_eq!(a.len(), pa.len() as u128);
+      for point in -25..30 {
+        assert_eq!(a.contains(point), pa.contains(&point));
+      }
+
+      // Same points, same representation
+      let rebuilt = pa.iter().map(|&p| (p, p)).collect::<IntervalSet<_>>();
+      assert_eq!(rebuilt, a);
+
+      let mut inserted = a.clone();
+      for (lo, hi) in b.iter() {
+        inserted.insert(lo, hi);
+      }
+      assert_eq!(inserted, a.union(&b));
+    }
+  }
+}
@@ -1,3 +1,6 @@
+#[macro_use]
+mod trace;
+
 mod day_1;
 mod day_10;
 mod day_11;
@@ -23,13 +26,56 @@ mod day_7;
 mod day_8;
 mod day_9;
 
+mod checked;
+mod cycle;
 mod fs;
+mod interval_set;
+mod newton;
+mod sequence;
+#[cfg(test)]
+mod test_util;
+
+use std::{env, io};
+
+use crate::{day_24::*, trace::Level};
+
+// -v for progress, -v -v (or -vv) for everything, --trace <file> to keep it all in a file
+fn parse_args() -> io::Result<(Level, Option<String>)> {
+  let mut level = Level::Off;
+  let mut path = None;
+  let mut args = env::args().skip(1);
 
-use crate::day_24::*;
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "-v" | "--verbose" if level == Level::Off => level = Level::Info,
+      "-v" | "--verbose" | "-vv" => level = Level::Debug,
+      "--trace" => {
+        path = Some(args.next().ok_or(io::Error::new(
+          io::ErrorKind::InvalidInput,
+          "--trace needs a file",
+        ))?);
+        level = Level::Debug;
+      }
+      _ => {
+        return Err(io::Error::new(
+          io::ErrorKind::InvalidInput,
+          format!("Unknown argument {}", arg),
+        ))
+      }
+    }
+  }
 
-fn main() {
-  println!("Start");
+  Ok((level, path))
+}
+
+fn main() -> io::Result<()> {
+  let (level, path) = parse_args()?;
+  trace::init(level, path.as_deref())?;
+
+  trace!(Level::Info, "Start");
   println!("Part 1: {:?}", part_1());
   println!("Part 2: {:?}", part_2());
-  println!("End");
+  trace!(Level::Info, "End");
+
+  Ok(())
 }
@@ -0,0 +1,228 @@
+use std::io;
+
+use nalgebra::{DMatrix, DVector};
+
+use crate::trace::Level;
+
+type Residual<'a> = Box<dyn Fn(&DVector<f64>) -> DVector<f64> + 'a>;
+type Jacobian<'a> = Box<dyn Fn(&DVector<f64>) -> DMatrix<f64> + 'a>;
+
+// How the solver got to its answer, one entry per iteration
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+  pub iterations: usize,
+  pub residual_norms: Vec<f64>,
+  // Fraction of the full Newton step that was taken
+  pub step_fractions: Vec<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Solution {
+  pub root: DVector<f64>,
+  pub diagnostics: Diagnostics,
+}
+
+// Newton–Raphson for f(x) = 0 with f: Rⁿ → Rⁿ. Without an analytic Jacobian one
+// is estimated with central differences. With the line search on, steps are
+// halved until the residual actually shrinks, which keeps bad guesses from
+// flying off.
+pub struct NewtonSolver<'a> {
+  residual: Residual<'a>,
+  jacobian: Option<Jacobian<'a>>,
+  tolerance: f64,
+  max_iterations: usize,
+  line_search: bool,
+}
+
+impl<'a> NewtonSolver<'a> {
+  pub fn new(residual: impl Fn(&DVector<f64>) -> DVector<f64> + 'a) -> Self {
+    Self {
+      residual: Box::new(residual),
+      jacobian: None,
+      tolerance: 1e-9,
+      max_iterations: 100,
+      line_search: true,
+    }
+  }
+
+  pub fn jacobian(mut self, jacobian: impl Fn(&DVector<f64>) -> DMatrix<f64> + 'a) -> Self {
+    self.jacobian = Some(Box::new(jacobian));
+    self
+  }
+
+  pub fn tolerance(mut self, tolerance: f64) -> Self {
+    self.tolerance = tolerance;
+    self
+  }
+
+  pub fn max_iterations(mut self, max_iterations: usize) -> Self {
+    self.max_iterations = max_iterations;
+    self
+  }
+
+  pub fn line_search(mut self, line_search: bool) -> Self {
+    self.line_search = line_search;
+    self
+  }
+
+  fn jacobian_at(&self, x: &DVector<f64>, fx: &DVector<f64>) -> DMatrix<f64> {
+    if let Some(jacobian) = &self.jacobian {
+      return jacobian(x);
+    }
+
+    let mut result = DMatrix::zeros(fx.len(), x.len());
+    for col in 0..x.len() {
+      // Scaled so the step is still visible next to big coordinates
+      let h = f64::EPSILON.cbrt() * x[col].abs().max(1.0);
+      let mut forward = x.clone();
+      let mut backward = x.clone();
+      forward[col] += h;
+      backward[col] -= h;
+      let derivative = ((self.residual)(&forward) - (self.residual)(&backward)) / (2.0 * h);
+      result.set_column(col, &derivative);
+    }
+    result
+  }
+
+  pub fn solve(&self, initial_guess: DVector<f64>) -> io::Result<Solution> {
+    let error = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+
+    let mut x = initial_guess;
+    let mut fx = (self.residual)(&x);
+    let mut diagnostics = Diagnostics::default();
+
+    for iteration in 0..=self.max_iterations {
+      let norm = fx.norm();
+      diagnostics.residual_norms.push(norm);
+      trace!(
+        Level::Debug,
+        "Newton iteration {}: |f| = {:e}",
+        iteration,
+        norm
+      );
+
+      if !norm.is_finite() {
+        return Err(error(format!(
+          "Residual is not finite after {} iterations",
+          iteration
+        )));
+      }
+      if norm < self.tolerance {
+        diagnostics.iterations = iteration;
+        return Ok(Solution {
+          root: x,
+          diagnostics,
+        });
+      }
+      if iteration == self.max_iterations {
+        break;
+      }
+
+      let step = self
+        .jacobian_at(&x, &fx)
+        .lu()
+        .solve(&fx)
+        .ok_or(error(format!(
+          "Jacobian is singular after {} iterations",
+          iteration
+        )))?;
+
+      let mut fraction = 1.0;
+      let mut next = &x - &step;
+      let mut f_next = (self.residual)(&next);
+      if self.line_search {
+        // NaN counts as not shrinking too
+        while f_next.norm().is_nan() || f_next.norm() >= norm {
+          fraction /= 2.0;
+          if fraction < 1e-12 {
+            return Err(error(format!(
+              "Line search stalled after {} iterations at |f| = {:e}",
+              iteration, norm
+            )));
+          }
+          next = &x - &step * fraction;
+          f_next = (self.residual)(&next);
+        }
+      }
+
+      diagnostics.step_fractions.push(fraction);
+      x = next;
+      fx = f_next;
+    }
+
+    Err(error(format!(
+      "No convergence after {} iterations, |f| = {:e}",
+      self.max_iterations,
+      fx.norm()
+    )))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // x² + y² = 4 and x = y
+  fn circle(v: &DVector<f64>) -> DVector<f64> {
+    DVector::from_vec(vec![v[0] * v[0] + v[1] * v[1] - 4.0, v[0] - v[1]])
+  }
+
+  #[test]
+  fn finds_roots() {
+    let expected = 2f64.sqrt();
+
+    let numeric = NewtonSolver::new(circle)
+      .solve(DVector::from_vec(vec![1.0, 3.0]))
+      .unwrap();
+    assert!((numeric.root[0] - expected).abs() < 1e-9);
+    assert!((numeric.root[1] - expected).abs() < 1e-9);
+
+    let analytic = NewtonSolver::new(circle)
+      .jacobian(|v| DMatrix::from_row_slice(2, 2, &[2.0 * v[0], 2.0 * v[1], 1.0, -1.0]))
+      .solve(DVector::from_vec(vec![1.0, 3.0]))
+      .unwrap();
+    assert!((analytic.root - numeric.root).norm() < 1e-9);
+
+    let diagnostics = analytic.diagnostics;
+    assert_eq!(diagnostics.residual_norms.len(), diagnostics.iterations + 1);
+    assert_eq!(diagnostics.step_fractions.len(), diagnostics.iterations);
+    assert!(diagnostics.residual_norms.windows(2).all(|w| w[1] < w[0]));
+  }
+
+  #[test]
+  fn damps_overshooting_steps() {
+    // Plain Newton on atan cycles or diverges from far enough out
+    let atan = |v: &DVector<f64>| v.map(f64::atan);
+    let start = DVector::from_vec(vec![3.0]);
+
+    assert!(NewtonSolver::new(atan)
+      .line_search(false)
+      .max_iterations(50)
+      .solve(start.clone())
+      .is_err());
+
+    let damped = NewtonSolver::new(atan).solve(start).unwrap();
+    assert!(damped.root[0].abs() < 1e-9);
+    assert!(damped.diagnostics.step_fractions[0] < 1.0);
+  }
+
+  #[test]
+  fn reports_failures() {
+    // Flat at the starting point
+    let singular = NewtonSolver::new(|v: &DVector<f64>| v.map(|x| x * x - 1.0))
+      .jacobian(|v| DMatrix::from_element(1, 1, 2.0 * v[0]))
+      .solve(DVector::from_vec(vec![0.0]));
+    assert!(singular.is_err());
+
+    // No real root, so it can never get there
+    let rootless = NewtonSolver::new(|v: &DVector<f64>| v.map(|x| x * x + 1.0))
+      .line_search(false)
+      .max_iterations(20)
+      .solve(DVector::from_vec(vec![0.5]));
+    assert!(rootless.is_err());
+
+    let stalled = NewtonSolver::new(|v: &DVector<f64>| v.map(|x| x * x + 1.0))
+      .solve(DVector::from_vec(vec![0.5]));
+    assert!(stalled.is_err());
+  }
+}
@@ -0,0 +1,134 @@
+use std::io;
+
+use crate::checked::{checked, narrow};
+
+// The lowest degree polynomial through values taken at x = 0, 1, 2, ..., stored
+// as the leading entries of its forward difference table (Newton's form). Any
+// point is then a sum of binomial coefficients, without rebuilding the table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Polynomial {
+  differences: Vec<i128>,
+  len: usize,
+  confirmed: bool,
+}
+
+impl Polynomial {
+  pub fn fit(values: &[i64]) -> io::Result<Polynomial> {
+    let mut row = values.iter().map(|&v| v as i128).collect::<Vec<_>>();
+    let mut differences = vec![];
+    let mut confirmed = false;
+
+    while let Some(&first) = row.first() {
+      if row.iter().all(|&v| v == 0) {
+        confirmed = true;
+        break;
+      }
+      differences.push(first);
+      row = row
+        .windows(2)
+        .map(|pair| checked(pair[1].checked_sub(pair[0]), "Difference"))
+        .collect::<io::Result<_>>()?;
+    }
+
+    // Newton's form is unique, so trailing zeros don't add to the degree
+    while differences.last() == Some(&0) {
+      differences.pop();
+    }
+
+    Ok(Polynomial {
+      differences,
+      len: values.len(),
+      confirmed,
+    })
+  }
+
+  pub fn degree(&self) -> usize {
+    self.differences.len().saturating_sub(1)
+  }
+
+  // Whether the differences ran out to zeros. Otherwise any n values fit a
+  // polynomial of degree n - 1 and nothing was left over to check it with.
+  pub fn is_polynomial(&self) -> bool {
+    self.confirmed
+  }
+
+  // f(x) = Σ Δᵏf(0)·C(x, k), which works for negative x too
+  pub fn at(&self, x: i64) -> io::Result<i64> {
+    let x = x as i128;
+    let mut binomial: i128 = 1;
+    let mut result: i128 = 0;
+
+    for (k, &difference) in self.differences.iter().enumerate() {
+      if k > 0 {
+        // C(x, k) = C(x, k - 1)·(x - k + 1)/k always divides exactly
+        let k = k as i128;
+        binomial = checked(binomial.checked_mul(x - k + 1), "Binomial")? / k;
+      }
+      let term = checked(difference.checked_mul(binomial), "Extrapolation")?;
+      result = checked(result.checked_add(term), "Extrapolation")?;
+    }
+
+    narrow(result)
+  }
+
+  // k steps past the last value
+  pub fn forward(&self, k: i64) -> io::Result<i64> {
+    self.at(checked((self.len as i64 - 1).checked_add(k), "Position")?)
+  }
+
+  // k steps before the first value
+  pub fn backward(&self, k: i64) -> io::Result<i64> {
+    self.at(checked(k.checked_neg(), "Position")?)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn extrapolates_both_ways() {
+    let polynomial = Polynomial::fit(&[10, 13, 16, 21, 30, 45]).unwrap();
+    assert_eq!(polynomial.degree(), 3);
+    assert!(polynomial.is_polynomial());
+    assert_eq!(polynomial.forward(1).unwrap(), 68);
+    assert_eq!(polynomial.backward(1).unwrap(), 5);
+
+    // n² + 1
+    let square = Polynomial::fit(&[1, 2, 5, 10]).unwrap();
+    assert_eq!(square.degree(), 2);
+    for x in -50..50 {
+      assert_eq!(square.at(x).unwrap(), x * x + 1);
+    }
+    assert_eq!(square.forward(1000).unwrap(), 1003 * 1003 + 1);
+    assert_eq!(square.backward(7).unwrap(), 50);
+
+    assert_eq!(Polynomial::fit(&[0, 0, 0]).unwrap().degree(), 0);
+    assert_eq!(Polynomial::fit(&[0, 0, 0]).unwrap().forward(5).unwrap(), 0);
+    assert_eq!(Polynomial::fit(&[4, 4]).unwrap().forward(5).unwrap(), 4);
+  }
+
+  #[test]
+  fn flags_sequences_too_short_to_tell() {
+    // Powers of two never settle, so the best fit uses every value
+    let powers = Polynomial::fit(&[1, 2, 4, 8, 16]).unwrap();
+    assert!(!powers.is_polynomial());
+    assert_eq!(powers.degree(), 4);
+    assert_eq!(powers.at(4).unwrap(), 16);
+
+    assert!(!Polynomial::fit(&[7]).unwrap().is_polynomial());
+    assert!(Polynomial::fit(&[7, 7]).unwrap().is_polynomial());
+    assert!(!Polynomial::fit(&[]).unwrap().is_polynomial());
+  }
+
+  #[test]
+  fn reports_overflow() {
+    let steep = Polynomial::fit(&[0, i64::MAX / 2, i64::MAX - 1]).unwrap();
+    assert_eq!(steep.at(2).unwrap(), i64::MAX - 1);
+    assert!(steep.forward(1).is_err());
+    assert!(steep.backward(i64::MIN).is_err());
+
+    let wide = Polynomial::fit(&[i64::MIN, i64::MAX, i64::MIN]).unwrap();
+    assert!(wide.forward(1).is_err());
+  }
+}
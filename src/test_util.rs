@@ -0,0 +1,16 @@
+// Small deterministic generator for randomised tests, so failures are reproducible
+pub struct Rng(u64);
+
+impl Rng {
+  pub fn new() -> Self {
+    Self(0x2545f4914f6cdd1d)
+  }
+
+  // A value in 0..below
+  pub fn next(&mut self, below: u64) -> u64 {
+    self.0 ^= self.0 << 13;
+    self.0 ^= self.0 >> 7;
+    self.0 ^= self.0 << 17;
+    self.0 % below
+  }
+}
@@ -0,0 +1,105 @@
+use std::{
+  fmt,
+  fs::File,
+  io::{self, Write},
+  sync::Mutex,
+};
+
+use once_cell::sync::Lazy;
+
+// Diagnostic output for the solvers. Nothing is written unless the runner asks
+// for it, and it never goes to stdout so it can't get mixed up with the answers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+  Off,
+  Info,
+  Debug,
+}
+
+enum Sink {
+  Stderr,
+  File(File),
+}
+
+struct Tracer {
+  level: Level,
+  sink: Sink,
+}
+
+static TRACER: Lazy<Mutex<Tracer>> = Lazy::new(|| {
+  Mutex::new(Tracer {
+    level: Level::Off,
+    sink: Sink::Stderr,
+  })
+});
+
+// Writes to stderr, or to the file at `path` when there is one
+pub fn init(level: Level, path: Option<&str>) -> io::Result<()> {
+  let sink = match path {
+    Some(path) => Sink::File(File::create(path)?),
+    None => Sink::Stderr,
+  };
+
+  let mut tracer = TRACER.lock().unwrap();
+  tracer.level = level;
+  tracer.sink = sink;
+  Ok(())
+}
+
+pub fn level() -> Level {
+  TRACER.lock().unwrap().level
+}
+
+pub fn enabled(level: Level) -> bool {
+  level != Level::Off && level <= TRACER.lock().unwrap().level
+}
+
+pub fn write(level: Level, args: fmt::Arguments) {
+  let mut tracer = TRACER.lock().unwrap();
+  if level == Level::Off || level > tracer.level {
+    return;
+  }
+
+  // Losing a trace line is not worth failing a solver over
+  let _ = match &mut tracer.sink {
+    Sink::Stderr => writeln!(io::stderr(), "{}", args),
+    Sink::File(file) => writeln!(file, "{}", args),
+  };
+}
+
+macro_rules! trace {
+  ($level:expr, $($arg:tt)*) => {
+    $crate::trace::write($level, format_args!($($arg)*))
+  };
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn filters_by_level() {
+    // Unique per run so parallel runs don't share the file
+    let path = std::env::temp_dir().join(format!(
+      "advent_of_code_trace_test_{}.txt",
+      std::process::id()
+    ));
+    let previous = level();
+    init(Level::Info, path.to_str()).unwrap();
+
+    trace!(Level::Info, "shown {}", 1);
+    trace!(Level::Debug, "hidden {}", 2);
+    assert!(enabled(Level::Info));
+    assert!(!enabled(Level::Debug));
+
+    init(Level::Off, None).unwrap();
+    trace!(Level::Info, "also hidden");
+
+    let written = std::fs::read_to_string(&path).unwrap();
+    init(previous, None).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(written.contains("shown 1\n"));
+    assert!(!written.contains("hidden"));
+  }
+}